@@ -0,0 +1,125 @@
+use std::fs;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use logwatcher::{
+    Delimiter, LogWatcher, LogWatcherAction, LogWatcherEvent, LogWatcherEventRef, StartPosition,
+};
+
+const LINES: usize = 100_000;
+
+/// Writes an access log of `LINES` lines ending in `delimiter`.
+fn access_log(name: &str, delimiter: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("logwatcher-{}-{}", name, std::process::id()));
+    let mut f = fs::File::create(&path).unwrap();
+    for i in 0..LINES {
+        write!(
+            f,
+            "10.0.{}.{} - - [10/Oct/2024:13:55:36 +0000] \"GET /api/v1/items/{} HTTP/1.1\" \
+             200 {} \"-\" \"Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36\"",
+            i / 256 % 256,
+            i % 256,
+            i,
+            i * 7 % 10_000
+        )
+        .unwrap();
+        f.write_all(delimiter).unwrap();
+    }
+    path
+}
+
+/// Reads the whole file from the start and returns how many lines it has.
+fn read_all(path: &Path, delimiter: &Delimiter) -> usize {
+    let mut log_watcher = LogWatcher::builder()
+        .start(StartPosition::Beginning)
+        .delimiter(delimiter.clone())
+        .register(path)
+        .unwrap();
+    let mut lines = 0;
+    while let Some(event) = log_watcher.next_timeout(Duration::ZERO) {
+        if let Ok(LogWatcherEvent::Line(..)) = event {
+            lines += 1;
+        }
+    }
+    lines
+}
+
+/// How lines used to be read, for comparison: one `read_line` at a time
+/// into a new `String`, seeking to the end of the line after each one,
+/// which throws away what `BufReader` has read ahead.
+fn read_all_per_line_seek(path: &Path) -> usize {
+    let mut reader = BufReader::new(fs::File::open(path).unwrap());
+    let mut pos = 0;
+    let mut lines = 0;
+    loop {
+        let mut line = String::new();
+        let len = reader.read_line(&mut line).unwrap();
+        if len == 0 {
+            return lines;
+        }
+        pos += len as u64;
+        reader.seek(SeekFrom::Start(pos)).unwrap();
+        lines += 1;
+    }
+}
+
+/// Like `read_all`, but with lines borrowed through `watch_borrowed`.
+fn read_all_borrowed(path: &Path) -> usize {
+    let mut log_watcher = LogWatcher::register_at(path, StartPosition::Beginning).unwrap();
+    let mut lines = 0;
+    log_watcher
+        .watch_borrowed(&mut |event| {
+            if let Ok(LogWatcherEventRef::Line(..)) = event {
+                lines += 1;
+            }
+            if lines == LINES {
+                LogWatcherAction::Stop
+            } else {
+                LogWatcherAction::None
+            }
+        })
+        .unwrap();
+    lines
+}
+
+fn bench_read(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read");
+    let cases = [
+        ("newline", Delimiter::Newline, &b"\n"[..]),
+        ("crlf", Delimiter::CrLf, &b"\r\n"[..]),
+        (
+            "sequence",
+            Delimiter::Sequence(b"<EOR>".to_vec()),
+            &b"<EOR>"[..],
+        ),
+    ];
+    for (name, delimiter, bytes) in cases {
+        let path = access_log(name, bytes);
+        let len = fs::metadata(&path).unwrap().len();
+        group.throughput(Throughput::Bytes(len));
+        group.bench_function(name, |b| {
+            b.iter(|| assert_eq!(read_all(&path, &delimiter), LINES));
+        });
+        fs::remove_file(&path).unwrap();
+    }
+    let path = access_log("borrowed", b"\n");
+    group.throughput(Throughput::Bytes(fs::metadata(&path).unwrap().len()));
+    group.bench_function("borrowed", |b| {
+        b.iter(|| assert_eq!(read_all_borrowed(&path), LINES));
+    });
+    group.bench_function("per-line-seek", |b| {
+        b.iter(|| assert_eq!(read_all_per_line_seek(&path), LINES));
+    });
+    fs::remove_file(&path).unwrap();
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bench_read
+}
+criterion_main!(benches);
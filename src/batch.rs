@@ -0,0 +1,232 @@
+use std::time::{Duration, Instant};
+
+use crate::checkpoint::Checkpoint;
+use crate::{LogWatcherError, LogWatcherEvent};
+
+/// When `LogWatcher::watch_batched` hands over the events collected so
+/// far: once there are as many lines as the maximum, once the lines add up
+/// to the maximum number of bytes, or once the first event has waited for
+/// the timeout, whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batch {
+    max_lines: usize,
+    max_bytes: usize,
+    timeout: Duration,
+}
+
+impl Batch {
+    /// At most 1000 lines or 1 MiB per batch, and a timeout of one second.
+    pub fn new() -> Batch {
+        Batch {
+            max_lines: 1000,
+            max_bytes: 1024 * 1024,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    pub fn max_lines(mut self, max_lines: usize) -> Batch {
+        self.max_lines = max_lines.max(1);
+        self
+    }
+
+    /// How many bytes of lines, not counting delimiters, fill a batch. The
+    /// line that gets there is the last one in the batch.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Batch {
+        self.max_bytes = max_bytes.max(1);
+        self
+    }
+
+    /// How long the first event of a batch waits for the rest.
+    pub fn timeout(mut self, timeout: Duration) -> Batch {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Default for Batch {
+    fn default() -> Batch {
+        Batch::new()
+    }
+}
+
+/// Collects events into batches.
+pub(crate) struct Collector {
+    config: Batch,
+    events: Vec<Result<LogWatcherEvent, LogWatcherError>>,
+    lines: usize,
+    bytes: usize,
+    /// When the batch is due by the timeout, unless it is empty or the
+    /// timeout is too long to have an end.
+    due: Option<Instant>,
+    /// Where reading stood before the first event of the batch.
+    start: Option<Checkpoint>,
+}
+
+impl Collector {
+    pub(crate) fn new(config: Batch) -> Collector {
+        Collector {
+            config,
+            events: Vec::new(),
+            lines: 0,
+            bytes: 0,
+            due: None,
+            start: None,
+        }
+    }
+
+    /// Adds an event. `start` is where reading stood before it.
+    pub(crate) fn push(
+        &mut self,
+        event: Result<LogWatcherEvent, LogWatcherError>,
+        start: Option<Checkpoint>,
+    ) {
+        if self.events.is_empty() {
+            self.due = Instant::now().checked_add(self.config.timeout);
+            self.start = start;
+        }
+        let len = match &event {
+            Ok(LogWatcherEvent::Line(line, _)) => Some(line.len()),
+            Ok(LogWatcherEvent::Bytes(line, _)) => Some(line.len()),
+            _ => None,
+        };
+        if let Some(len) = len {
+            self.lines += 1;
+            self.bytes += len;
+        }
+        self.events.push(event);
+    }
+
+    /// Whether the batch has reached a maximum or waited out the timeout.
+    pub(crate) fn full(&self) -> bool {
+        self.lines >= self.config.max_lines
+            || self.bytes >= self.config.max_bytes
+            || self.due.is_some_and(|due| Instant::now() >= due)
+    }
+
+    /// Hands over the batch and starts a new one.
+    pub(crate) fn take(&mut self) -> Vec<Result<LogWatcherEvent, LogWatcherError>> {
+        self.lines = 0;
+        self.bytes = 0;
+        self.due = None;
+        self.start = None;
+        std::mem::take(&mut self.events)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// When the batch is due by the timeout, if ever.
+    pub(crate) fn due_at(&self) -> Option<Instant> {
+        self.due
+    }
+
+    /// Where reading stood before the batch, if anything is in it.
+    pub(crate) fn start(&self) -> Option<Checkpoint> {
+        self.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::{FileId, LineInfo};
+
+    fn line(text: &str) -> Result<LogWatcherEvent, LogWatcherError> {
+        let info = LineInfo {
+            offset: 0,
+            end: text.len() as u64 + 1,
+            number: 0,
+            file: FileId::default(),
+            path: Arc::from(Path::new("app.log")),
+            read_at: SystemTime::now(),
+            unterminated: false,
+            truncated: false,
+        };
+        Ok(LogWatcherEvent::Line(text.to_string(), info))
+    }
+
+    fn missing() -> Result<LogWatcherEvent, LogWatcherError> {
+        Ok(LogWatcherEvent::FileMissing {
+            since: Duration::ZERO,
+        })
+    }
+
+    fn at(offset: u64) -> Option<Checkpoint> {
+        Some(Checkpoint {
+            dev: 1,
+            ino: 2,
+            offset,
+        })
+    }
+
+    fn no_timeout() -> Batch {
+        Batch::new().timeout(Duration::from_secs(3600))
+    }
+
+    #[test]
+    fn full_at_max_lines() {
+        let mut collector = Collector::new(no_timeout().max_lines(2));
+        collector.push(line("a"), None);
+        collector.push(missing(), None);
+        assert!(!collector.full());
+        collector.push(line("b"), None);
+        assert!(collector.full());
+    }
+
+    #[test]
+    fn full_at_max_bytes() {
+        let mut collector = Collector::new(no_timeout().max_bytes(5));
+        collector.push(line("abc"), None);
+        assert!(!collector.full());
+        collector.push(line("de"), None);
+        assert!(collector.full());
+    }
+
+    #[test]
+    fn full_after_the_timeout() {
+        let mut collector = Collector::new(Batch::new().timeout(Duration::ZERO));
+        assert!(!collector.full());
+        collector.push(missing(), None);
+        assert!(collector.full());
+    }
+
+    #[test]
+    fn never_full_without_a_timeout() {
+        let mut collector = Collector::new(Batch::new().timeout(Duration::MAX));
+        collector.push(missing(), None);
+        assert_eq!(collector.due_at(), None);
+        assert!(!collector.full());
+    }
+
+    #[test]
+    fn start_is_where_the_batch_began() {
+        let mut collector = Collector::new(no_timeout());
+        assert_eq!(collector.start(), None);
+        collector.push(line("a"), at(10));
+        collector.push(line("b"), at(12));
+        assert_eq!(collector.start(), at(10));
+    }
+
+    #[test]
+    fn take_starts_a_new_batch() {
+        let mut collector = Collector::new(no_timeout().max_lines(2).max_bytes(4));
+        collector.push(line("abc"), at(0));
+        collector.push(missing(), at(4));
+        let batch = collector.take();
+        assert_eq!(batch.len(), 2);
+        assert!(collector.is_empty());
+        assert_eq!(collector.start(), None);
+        assert_eq!(collector.due_at(), None);
+
+        // Nothing from the batch before counts towards the maximums.
+        collector.push(line("abc"), at(4));
+        assert!(!collector.full());
+        assert_eq!(collector.start(), at(4));
+        assert!(collector.due_at().is_some());
+    }
+}
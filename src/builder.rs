@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::checkpoint::CheckpointFile;
+use crate::follower::DEFAULT_BUFFER_SIZE;
+use crate::{
+    Delimiter, EncodingPolicy, LineLimit, LogWatcher, LogWatcherError, Multiline, RetryPolicy,
+    RotationDetection, StartPosition, WatchMode,
+};
+
+/// Sets up a `LogWatcher` with everything configurable in one place,
+/// starting from `LogWatcher::builder()`. Options that are not set keep the
+/// defaults `LogWatcher::register` uses.
+#[derive(Debug, Clone)]
+pub struct LogWatcherBuilder {
+    mode: WatchMode,
+    start: StartPosition,
+    allow_missing: bool,
+    state_file: Option<PathBuf>,
+    retry_policy: RetryPolicy,
+    missing_timeout: Option<Duration>,
+    buffer_size: usize,
+    delimiter: Delimiter,
+    encoding: EncodingPolicy,
+    flush_timeout: Option<Duration>,
+    line_limit: Option<LineLimit>,
+    multiline: Option<Multiline>,
+    rotation: RotationDetection,
+}
+
+impl LogWatcherBuilder {
+    pub fn new() -> LogWatcherBuilder {
+        LogWatcherBuilder {
+            mode: WatchMode::default(),
+            start: StartPosition::default(),
+            allow_missing: false,
+            state_file: None,
+            retry_policy: RetryPolicy::default(),
+            missing_timeout: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            delimiter: Delimiter::default(),
+            encoding: EncodingPolicy::default(),
+            flush_timeout: None,
+            line_limit: None,
+            multiline: None,
+            rotation: RotationDetection::default(),
+        }
+    }
+
+    /// How changes to the file are noticed, inotify by default.
+    pub fn watch_mode(mut self, mode: WatchMode) -> LogWatcherBuilder {
+        self.mode = mode;
+        self
+    }
+
+    /// Checks the file for changes every `interval` instead of waiting for
+    /// inotify. Short for `watch_mode(WatchMode::Poll(interval))`.
+    pub fn poll_interval(self, interval: Duration) -> LogWatcherBuilder {
+        self.watch_mode(WatchMode::Poll(interval))
+    }
+
+    /// Where to start reading, the end of the file by default. Ignored for
+    /// a file with a checkpoint.
+    pub fn start(mut self, start: StartPosition) -> LogWatcherBuilder {
+        self.start = start;
+        self
+    }
+
+    /// Whether the file may not exist yet, as with
+    /// `LogWatcher::register_allow_missing`.
+    pub fn allow_missing(mut self, allow_missing: bool) -> LogWatcherBuilder {
+        self.allow_missing = allow_missing;
+        self
+    }
+
+    /// Keeps how far the file has been read in `state_file`, as with
+    /// `LogWatcher::register_with_checkpoint`.
+    pub fn checkpoint<P: AsRef<Path>>(mut self, state_file: P) -> LogWatcherBuilder {
+        self.state_file = Some(state_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// How failures are retried, see `LogWatcher::set_retry_policy`.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> LogWatcherBuilder {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// See `LogWatcher::set_missing_timeout`.
+    pub fn missing_timeout(mut self, timeout: Option<Duration>) -> LogWatcherBuilder {
+        self.missing_timeout = timeout;
+        self
+    }
+
+    /// How many bytes are read from the file at a time, 8 KiB by default.
+    pub fn buffer_size(mut self, size: usize) -> LogWatcherBuilder {
+        self.buffer_size = size;
+        self
+    }
+
+    /// See `LogWatcher::set_delimiter`.
+    pub fn delimiter(mut self, delimiter: Delimiter) -> LogWatcherBuilder {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// See `LogWatcher::set_encoding`.
+    pub fn encoding(mut self, encoding: EncodingPolicy) -> LogWatcherBuilder {
+        self.encoding = encoding;
+        self
+    }
+
+    /// See `LogWatcher::set_flush_timeout`.
+    pub fn flush_timeout(mut self, timeout: Option<Duration>) -> LogWatcherBuilder {
+        self.flush_timeout = timeout;
+        self
+    }
+
+    /// See `LogWatcher::set_line_limit`.
+    pub fn line_limit(mut self, limit: Option<LineLimit>) -> LogWatcherBuilder {
+        self.line_limit = limit;
+        self
+    }
+
+    /// See `LogWatcher::set_multiline`.
+    pub fn multiline(mut self, multiline: Option<Multiline>) -> LogWatcherBuilder {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Which kinds of rotation to look out for.
+    pub fn rotation_detection(mut self, rotation: RotationDetection) -> LogWatcherBuilder {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Registers `filename` with this configuration.
+    pub fn register<P: AsRef<Path>>(self, filename: P) -> Result<LogWatcher, LogWatcherError> {
+        let checkpoints = match &self.state_file {
+            Some(state_file) => Some(
+                CheckpointFile::load(state_file)
+                    .map_err(|e| LogWatcherError::checkpoint(state_file, e))?,
+            ),
+            None => None,
+        };
+        let mut log_watcher = LogWatcher::open(
+            filename.as_ref(),
+            self.mode,
+            self.start,
+            checkpoints,
+            self.allow_missing,
+            &self.delimiter,
+        )?;
+        let follower = &mut log_watcher.follower;
+        follower.set_buffer_size(self.buffer_size);
+        follower.set_rotation_detection(self.rotation);
+        follower.set_retry_policy(self.retry_policy);
+        follower.set_missing_timeout(self.missing_timeout);
+        follower.set_delimiter(self.delimiter);
+        follower.set_encoding(self.encoding);
+        follower.set_flush_timeout(self.flush_timeout);
+        follower.set_line_limit(self.line_limit);
+        follower.set_multiline(self.multiline);
+        Ok(log_watcher)
+    }
+}
+
+impl Default for LogWatcherBuilder {
+    fn default() -> LogWatcherBuilder {
+        LogWatcherBuilder::new()
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often positions are written while files are being read. Whatever
+/// is left is written shortly after the watcher catches up, and when it
+/// stops.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The identity of a file and how far into it the watcher has read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Checkpoint {
+    pub(crate) dev: u64,
+    pub(crate) ino: u64,
+    pub(crate) offset: u64,
+}
+
+/// Checkpoints for any number of watched files, kept in one state file.
+///
+/// Each line holds `<dev> <ino> <offset> <path>`. The file is replaced
+/// atomically on every save, so a crash leaves either the old or the new
+/// contents, never a mix.
+pub(crate) struct CheckpointFile {
+    path: PathBuf,
+    entries: HashMap<PathBuf, Checkpoint>,
+    changed: bool,
+    last_save: Option<Instant>,
+}
+
+impl CheckpointFile {
+    /// Reads the state file, or starts empty if it doesn't exist yet.
+    pub(crate) fn load(path: &Path) -> io::Result<CheckpointFile> {
+        let mut entries = HashMap::new();
+        match fs::read(path) {
+            Ok(data) => {
+                for line in data.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+                    let (filename, checkpoint) = parse(line).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("malformed checkpoint in {}", path.display()),
+                        )
+                    })?;
+                    entries.insert(filename, checkpoint);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        Ok(CheckpointFile {
+            path: path.to_path_buf(),
+            entries,
+            changed: false,
+            last_save: None,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn get(&self, filename: &Path) -> Option<Checkpoint> {
+        self.entries.get(filename).copied()
+    }
+
+    pub(crate) fn set(&mut self, filename: &Path, checkpoint: Checkpoint) {
+        // A newline in the path would break the line format.
+        if filename.as_os_str().as_bytes().contains(&b'\n') {
+            return;
+        }
+        if self.entries.get(filename) != Some(&checkpoint) {
+            self.entries.insert(filename.to_path_buf(), checkpoint);
+            self.changed = true;
+        }
+    }
+
+    pub(crate) fn remove(&mut self, filename: &Path) {
+        if self.entries.remove(filename).is_some() {
+            self.changed = true;
+        }
+    }
+
+    /// Whether enough time has passed since the last save to save again.
+    pub(crate) fn due(&self) -> bool {
+        self.last_save.is_none_or(|t| t.elapsed() >= SAVE_INTERVAL)
+    }
+
+    /// How long until a pending change may be written, if there is one.
+    pub(crate) fn due_in(&self) -> Option<Duration> {
+        if !self.changed {
+            return None;
+        }
+        let since = self.last_save.map_or(SAVE_INTERVAL, |t| t.elapsed());
+        Some(SAVE_INTERVAL.saturating_sub(since))
+    }
+
+    /// Writes the state file if anything changed. A failed write counts as
+    /// a save for `due`, so it isn't retried in a tight loop.
+    pub(crate) fn save(&mut self) -> io::Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.last_save = Some(Instant::now());
+
+        let mut data = Vec::new();
+        for (filename, c) in &self.entries {
+            write!(data, "{} {} {} ", c.dev, c.ino, c.offset)?;
+            data.extend_from_slice(filename.as_os_str().as_bytes());
+            data.push(b'\n');
+        }
+
+        let mut tmp = OsString::from(self.path.as_os_str());
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut f = File::create(&tmp)?;
+        f.write_all(&data)?;
+        f.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        // Make the rename itself durable.
+        let dir = match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+        self.changed = false;
+        Ok(())
+    }
+}
+
+fn parse(line: &[u8]) -> Option<(PathBuf, Checkpoint)> {
+    let mut fields = line.splitn(4, |&b| b == b' ');
+    let mut number = || -> Option<u64> { std::str::from_utf8(fields.next()?).ok()?.parse().ok() };
+    let checkpoint = Checkpoint {
+        dev: number()?,
+        ino: number()?,
+        offset: number()?,
+    };
+    let filename = fields.next().filter(|f| !f.is_empty())?;
+    let filename = PathBuf::from(OsString::from_vec(filename.to_vec()));
+    Some((filename, checkpoint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn checkpoint(offset: u64) -> Checkpoint {
+        Checkpoint {
+            dev: 64769,
+            ino: 1234567,
+            offset,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = TempFile::new(b"");
+        let paths = [
+            PathBuf::from("/var/log/app.log"),
+            PathBuf::from("/var/log/my app/access log.1"),
+            PathBuf::from(" leading and trailing "),
+            PathBuf::from("/var/log/jövő.log"),
+            PathBuf::from(OsString::from_vec(b"/var/log/\xff\xfe.log".to_vec())),
+        ];
+        let mut saved = CheckpointFile::load(state.path()).unwrap();
+        for (i, path) in paths.iter().enumerate() {
+            saved.set(path, checkpoint(i as u64 * 100));
+        }
+        saved.save().unwrap();
+
+        let loaded = CheckpointFile::load(state.path()).unwrap();
+        assert_eq!(loaded.entries.len(), paths.len());
+        for (i, path) in paths.iter().enumerate() {
+            assert_eq!(loaded.get(path), Some(checkpoint(i as u64 * 100)));
+        }
+    }
+
+    #[test]
+    fn remove_is_saved() {
+        let state = TempFile::new(b"");
+        let mut saved = CheckpointFile::load(state.path()).unwrap();
+        saved.set(Path::new("a.log"), checkpoint(1));
+        saved.set(Path::new("b.log"), checkpoint(2));
+        saved.save().unwrap();
+        saved.remove(Path::new("a.log"));
+        saved.save().unwrap();
+
+        let loaded = CheckpointFile::load(state.path()).unwrap();
+        assert_eq!(loaded.get(Path::new("a.log")), None);
+        assert_eq!(loaded.get(Path::new("b.log")), Some(checkpoint(2)));
+    }
+
+    #[test]
+    fn path_with_a_newline_is_not_kept() {
+        let state = TempFile::new(b"");
+        let mut saved = CheckpointFile::load(state.path()).unwrap();
+        saved.set(Path::new("a\nb.log"), checkpoint(1));
+        assert_eq!(saved.get(Path::new("a\nb.log")), None);
+        assert_eq!(saved.due_in(), None);
+    }
+
+    #[test]
+    fn missing_state_file_is_empty() {
+        let state = TempFile::new(b"");
+        fs::remove_file(state.path()).unwrap();
+        let loaded = CheckpointFile::load(state.path()).unwrap();
+        assert!(loaded.entries.is_empty());
+    }
+
+    #[test]
+    fn parse_line() {
+        let (path, c) = parse(b"1 2 3 /var/log/my app.log").unwrap();
+        assert_eq!(path, Path::new("/var/log/my app.log"));
+        assert_eq!(
+            c,
+            Checkpoint {
+                dev: 1,
+                ino: 2,
+                offset: 3
+            }
+        );
+    }
+
+    #[test]
+    fn parse_malformed_line() {
+        for line in [
+            &b"1 2 3"[..],
+            b"1 2 3 ",
+            b"1 2 /var/log/app.log",
+            b"1 x 3 /var/log/app.log",
+            b"-1 2 3 /var/log/app.log",
+            b"1  2 3 /var/log/app.log",
+            b"",
+        ] {
+            assert_eq!(parse(line), None, "{:?}", String::from_utf8_lossy(line));
+        }
+    }
+
+    #[test]
+    fn malformed_state_file_is_an_error() {
+        let state = TempFile::new(b"1 2 3 /var/log/app.log\nnot a checkpoint\n");
+        let err = CheckpointFile::load(state.path()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
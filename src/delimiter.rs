@@ -0,0 +1,117 @@
+use std::ops::Range;
+
+/// What ends a line. The delimiter is not part of the delivered line.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Delimiter {
+    /// `\n`. A `\r` before it stays part of the line.
+    #[default]
+    Newline,
+    /// `\n` with an optional `\r` before it, which is stripped as well, so
+    /// files mixing both line endings work.
+    CrLf,
+    /// A NUL byte, as written by `find -print0` and the like.
+    Nul,
+    Byte(u8),
+    /// A sequence of bytes. An empty sequence is taken as `\n`.
+    Sequence(Vec<u8>),
+}
+
+impl Delimiter {
+    /// How long the first line in `data` is with its delimiter, or `None`
+    /// if there is no delimiter in it yet.
+    pub(crate) fn find(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            Delimiter::Sequence(seq) if seq.len() > 1 => {
+                memchr::memmem::find(data, seq).map(|i| i + seq.len())
+            }
+            _ => memchr::memchr(self.last_byte(), data).map(|i| i + 1),
+        }
+    }
+
+    /// Where the last delimiter in `data` is, if there is one.
+    pub(crate) fn rfind(&self, data: &[u8]) -> Option<Range<usize>> {
+        match self {
+            Delimiter::Sequence(seq) if seq.len() > 1 => {
+                memchr::memmem::rfind(data, seq).map(|i| i..i + seq.len())
+            }
+            _ => memchr::memrchr(self.last_byte(), data).map(|i| i..i + 1),
+        }
+    }
+
+    /// How long the delimiter at the end of `line` is, or 0 if `line` is
+    /// not terminated.
+    pub(crate) fn terminator_len(&self, line: &[u8]) -> usize {
+        match self {
+            Delimiter::CrLf if line.ends_with(b"\r\n") => 2,
+            Delimiter::Sequence(seq) if !seq.is_empty() => {
+                if line.ends_with(seq) {
+                    seq.len()
+                } else {
+                    0
+                }
+            }
+            _ => usize::from(line.last() == Some(&self.last_byte())),
+        }
+    }
+
+    /// The most bytes the delimiter can take up.
+    pub(crate) fn max_len(&self) -> usize {
+        match self {
+            Delimiter::CrLf => 2,
+            Delimiter::Sequence(seq) => seq.len().max(1),
+            Delimiter::Newline | Delimiter::Nul | Delimiter::Byte(_) => 1,
+        }
+    }
+
+    fn last_byte(&self) -> u8 {
+        match self {
+            Delimiter::Newline | Delimiter::CrLf => b'\n',
+            Delimiter::Nul => 0,
+            Delimiter::Byte(b) => *b,
+            Delimiter::Sequence(seq) => seq.last().copied().unwrap_or(b'\n'),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seq(s: &str) -> Delimiter {
+        Delimiter::Sequence(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn find_includes_the_delimiter() {
+        assert_eq!(Delimiter::Newline.find(b"ab\ncd\n"), Some(3));
+        assert_eq!(Delimiter::CrLf.find(b"ab\r\ncd"), Some(4));
+        assert_eq!(Delimiter::CrLf.find(b"ab\ncd"), Some(3));
+        assert_eq!(Delimiter::Nul.find(b"ab\0cd"), Some(3));
+        assert_eq!(Delimiter::Byte(b';').find(b"ab;cd"), Some(3));
+        assert_eq!(seq("<EOR>").find(b"ab<EO<EOR>cd"), Some(10));
+        assert_eq!(seq("").find(b"ab\ncd"), Some(3));
+        assert_eq!(Delimiter::Newline.find(b"abcd"), None);
+        assert_eq!(seq("<EOR>").find(b"ab<EOR"), None);
+    }
+
+    #[test]
+    fn rfind_finds_the_last_delimiter() {
+        assert_eq!(Delimiter::Newline.rfind(b"a\nb\nc"), Some(3..4));
+        assert_eq!(Delimiter::CrLf.rfind(b"a\r\nb\r\n"), Some(5..6));
+        assert_eq!(seq("<EOR>").rfind(b"a<EOR>b<EOR>c"), Some(7..12));
+        assert_eq!(seq("<EOR>").rfind(b"a<EOR>b<EO"), Some(1..6));
+        assert_eq!(Delimiter::Nul.rfind(b"abc"), None);
+    }
+
+    #[test]
+    fn terminator_len() {
+        assert_eq!(Delimiter::Newline.terminator_len(b"ab\r\n"), 1);
+        assert_eq!(Delimiter::CrLf.terminator_len(b"ab\r\n"), 2);
+        assert_eq!(Delimiter::CrLf.terminator_len(b"ab\n"), 1);
+        assert_eq!(seq("<EOR>").terminator_len(b"ab<EOR>"), 5);
+        assert_eq!(seq("<EOR>").terminator_len(b"ab>"), 0);
+        assert_eq!(seq("").terminator_len(b"ab\n"), 1);
+        assert_eq!(Delimiter::Newline.terminator_len(b"ab"), 0);
+        assert_eq!(Delimiter::Newline.terminator_len(b""), 0);
+    }
+}
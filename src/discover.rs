@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+
+/// Which files in a directory a `LogWatcherSet` should pick up on its own.
+///
+/// Patterns use glob syntax (`*`, `?`, `[...]`) and are matched against
+/// file names only. A file is followed when it matches at least one
+/// include pattern (or there are none) and no exclude pattern. Exclude
+/// rotated copies such as `*.1` or `*.gz` if the include patterns would
+/// otherwise catch them, or they will be read again as new files.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    dir: PathBuf,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl Discovery {
+    /// Every file in `dir`, before include and exclude rules.
+    pub fn dir<P: AsRef<Path>>(dir: P) -> Discovery {
+        Discovery {
+            dir: dir.as_ref().to_path_buf(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Files matching a pattern such as `/var/log/app/worker-*.log`.
+    /// Wildcards are only supported in the file name, not in the
+    /// directory part.
+    pub fn glob(pattern: &str) -> Result<Discovery, io::Error> {
+        let path = Path::new(pattern);
+        let dir = match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        if dir.to_string_lossy().contains(['*', '?', '[']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("wildcards are only supported in the file name: {}", pattern),
+            ));
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Discovery::dir(dir).include(&name)
+    }
+
+    pub fn include(mut self, pattern: &str) -> Result<Discovery, io::Error> {
+        self.include.push(compile(pattern)?);
+        Ok(self)
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Result<Discovery, io::Error> {
+        self.exclude.push(compile(pattern)?);
+        Ok(self)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn matches(&self, name: &OsStr) -> bool {
+        let name = name.to_string_lossy();
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(&name)))
+            && !self.exclude.iter().any(|p| p.matches(&name))
+    }
+
+    /// Lists the regular files in the directory that match.
+    pub(crate) fn scan(&self) -> io::Result<HashSet<PathBuf>> {
+        let mut found = HashSet::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !self.matches(&entry.file_name()) {
+                continue;
+            }
+            let path = entry.path();
+            // Follows symlinks, so a link to a log file counts as one.
+            if fs::metadata(&path).map(|m| m.is_file()).unwrap_or(false) {
+                found.insert(path);
+            }
+        }
+        Ok(found)
+    }
+}
+
+fn compile(pattern: &str) -> io::Result<Pattern> {
+    Pattern::new(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
@@ -0,0 +1,17 @@
+/// What to do with lines that are not valid UTF-8.
+///
+/// Whatever the policy, reading always moves past such a line, so a binary
+/// blob in a log can't stall the watcher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncodingPolicy {
+    /// Deliver `LogWatcherError::InvalidEncoding` in place of the line.
+    #[default]
+    Strict,
+    /// Deliver the line with invalid sequences replaced by U+FFFD.
+    Lossy,
+    /// Drop the line without a word.
+    Skip,
+    /// Don't decode at all: every line is delivered as
+    /// `LogWatcherEvent::Bytes`, valid UTF-8 or not.
+    Raw,
+}
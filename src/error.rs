@@ -0,0 +1,238 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong while following a file.
+///
+/// Errors about a watched file carry its path and the offset reading had
+/// reached, or was about to start at.
+#[derive(Debug)]
+pub enum LogWatcherError {
+    /// The file or directory could not be opened.
+    Open {
+        path: PathBuf,
+        offset: u64,
+        source: io::Error,
+    },
+    /// Opening or reading the file was not allowed.
+    PermissionDenied { path: PathBuf, offset: u64 },
+    /// Moving to a position in the file failed.
+    Seek {
+        path: PathBuf,
+        offset: u64,
+        source: io::Error,
+    },
+    /// Reading from the file failed.
+    Read {
+        path: PathBuf,
+        offset: u64,
+        source: io::Error,
+    },
+    /// The line starting at `offset` is not valid UTF-8. It is skipped;
+    /// see `EncodingPolicy` for other ways to handle it.
+    InvalidEncoding { path: PathBuf, offset: u64 },
+    /// The file went away while it was being read.
+    FileVanished { path: PathBuf, offset: u64 },
+    /// The file was rotated, but the file that replaced it could not be
+    /// opened. Reading stays with the old file until that succeeds.
+    Rotation {
+        path: PathBuf,
+        offset: u64,
+        source: io::Error,
+    },
+    /// Checkpoints could not be loaded from or saved to the state file at
+    /// `path`.
+    Checkpoint { path: PathBuf, source: io::Error },
+    /// The retry policy ran out after `attempts` failures in a row, each
+    /// of which was delivered before. The file is no longer followed.
+    GaveUp {
+        path: PathBuf,
+        offset: u64,
+        attempts: u32,
+    },
+    /// Setting up the watcher itself failed.
+    Io(io::Error),
+}
+
+impl LogWatcherError {
+    /// The file the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            LogWatcherError::Open { path, .. }
+            | LogWatcherError::PermissionDenied { path, .. }
+            | LogWatcherError::Seek { path, .. }
+            | LogWatcherError::Read { path, .. }
+            | LogWatcherError::InvalidEncoding { path, .. }
+            | LogWatcherError::FileVanished { path, .. }
+            | LogWatcherError::Rotation { path, .. }
+            | LogWatcherError::Checkpoint { path, .. }
+            | LogWatcherError::GaveUp { path, .. } => Some(path),
+            LogWatcherError::Io(_) => None,
+        }
+    }
+
+    /// How far into the file the error happened, if it is about a watched
+    /// file.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            LogWatcherError::Open { offset, .. }
+            | LogWatcherError::PermissionDenied { offset, .. }
+            | LogWatcherError::Seek { offset, .. }
+            | LogWatcherError::Read { offset, .. }
+            | LogWatcherError::InvalidEncoding { offset, .. }
+            | LogWatcherError::FileVanished { offset, .. }
+            | LogWatcherError::Rotation { offset, .. }
+            | LogWatcherError::GaveUp { offset, .. } => Some(*offset),
+            LogWatcherError::Checkpoint { .. } | LogWatcherError::Io(_) => None,
+        }
+    }
+
+    pub(crate) fn open(path: &Path, offset: u64, err: io::Error) -> LogWatcherError {
+        let path = path.to_path_buf();
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => LogWatcherError::PermissionDenied { path, offset },
+            _ => LogWatcherError::Open {
+                path,
+                offset,
+                source: err,
+            },
+        }
+    }
+
+    pub(crate) fn read(path: &Path, offset: u64, err: io::Error) -> LogWatcherError {
+        let path = path.to_path_buf();
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => LogWatcherError::PermissionDenied { path, offset },
+            io::ErrorKind::NotFound | io::ErrorKind::StaleNetworkFileHandle => {
+                LogWatcherError::FileVanished { path, offset }
+            }
+            _ => LogWatcherError::Read {
+                path,
+                offset,
+                source: err,
+            },
+        }
+    }
+
+    pub(crate) fn seek(path: &Path, offset: u64, err: io::Error) -> LogWatcherError {
+        LogWatcherError::Seek {
+            path: path.to_path_buf(),
+            offset,
+            source: err,
+        }
+    }
+
+    pub(crate) fn rotation(path: &Path, offset: u64, err: io::Error) -> LogWatcherError {
+        let path = path.to_path_buf();
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => LogWatcherError::PermissionDenied { path, offset },
+            _ => LogWatcherError::Rotation {
+                path,
+                offset,
+                source: err,
+            },
+        }
+    }
+
+    pub(crate) fn checkpoint(path: &Path, err: io::Error) -> LogWatcherError {
+        LogWatcherError::Checkpoint {
+            path: path.to_path_buf(),
+            source: err,
+        }
+    }
+}
+
+impl fmt::Display for LogWatcherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogWatcherError::Open { path, source, .. } => {
+                write!(f, "failed to open {}: {}", path.display(), source)
+            }
+            LogWatcherError::PermissionDenied { path, .. } => {
+                write!(f, "permission denied for {}", path.display())
+            }
+            LogWatcherError::Seek {
+                path,
+                offset,
+                source,
+            } => write!(
+                f,
+                "failed to seek to offset {} in {}: {}",
+                offset,
+                path.display(),
+                source
+            ),
+            LogWatcherError::Read {
+                path,
+                offset,
+                source,
+            } => write!(
+                f,
+                "failed to read {} at offset {}: {}",
+                path.display(),
+                offset,
+                source
+            ),
+            LogWatcherError::InvalidEncoding { path, offset } => write!(
+                f,
+                "invalid UTF-8 in {} at offset {}",
+                path.display(),
+                offset
+            ),
+            LogWatcherError::FileVanished { path, offset } => write!(
+                f,
+                "{} vanished after reading to offset {}",
+                path.display(),
+                offset
+            ),
+            LogWatcherError::Rotation { path, source, .. } => write!(
+                f,
+                "failed to open the rotated {}: {}",
+                path.display(),
+                source
+            ),
+            LogWatcherError::Checkpoint { path, source } => write!(
+                f,
+                "failed to access checkpoints in {}: {}",
+                path.display(),
+                source
+            ),
+            LogWatcherError::GaveUp {
+                path,
+                offset,
+                attempts,
+            } => write!(
+                f,
+                "gave up on {} at offset {} after {} failed attempts",
+                path.display(),
+                offset,
+                attempts
+            ),
+            LogWatcherError::Io(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl Error for LogWatcherError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LogWatcherError::Open { source, .. }
+            | LogWatcherError::Seek { source, .. }
+            | LogWatcherError::Read { source, .. }
+            | LogWatcherError::Rotation { source, .. }
+            | LogWatcherError::Checkpoint { source, .. }
+            | LogWatcherError::Io(source) => Some(source),
+            LogWatcherError::PermissionDenied { .. }
+            | LogWatcherError::InvalidEncoding { .. }
+            | LogWatcherError::FileVanished { .. }
+            | LogWatcherError::GaveUp { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for LogWatcherError {
+    fn from(err: io::Error) -> LogWatcherError {
+        LogWatcherError::Io(err)
+    }
+}
@@ -0,0 +1,1107 @@
+use std::fs;
+use std::fs::{File, Metadata};
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::checkpoint::Checkpoint;
+use crate::multiline::Joiner;
+use crate::reader::LineReader;
+use crate::retry::Backoff;
+use crate::rotation;
+use crate::{
+    Delimiter, EncodingPolicy, FileId, LineInfo, LineLimit, LogWatcherAction, LogWatcherError,
+    LogWatcherEvent, LogWatcherEventRef, Multiline, RetryPolicy, Rotation, RotationDetection,
+    RotationKind, StartPosition,
+};
+
+/// Read buffer size unless configured otherwise.
+pub(crate) const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// How often a missing file is reported again while it stays missing.
+const MISSING_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What `filename` points at, compared to the file being read.
+#[derive(Debug, PartialEq, Eq)]
+enum Reopen {
+    Unchanged,
+    Rotated,
+    Missing,
+}
+
+/// What the read loop came up with.
+enum Read {
+    Line(PendingLine),
+    Event(LogWatcherEvent),
+}
+
+/// A line at the start of the read buffer that is about to be handed out.
+struct PendingLine {
+    /// How much of the buffer it takes up.
+    len: usize,
+    /// How much of that is delivered, without the delimiter and anything
+    /// past the line limit.
+    keep: usize,
+    unterminated: bool,
+    truncated: bool,
+}
+
+/// Reading state for a single log file.
+///
+/// A follower never blocks: it hands out whatever has been written since
+/// the last call and reports `None` once it has caught up. Waiting for more
+/// data is left to the owner.
+pub(crate) struct Follower {
+    filename: Arc<Path>,
+    /// The file being read, unless `filename` didn't exist yet when the
+    /// follower was created.
+    id: Option<FileId>,
+    pos: u64,
+    /// Lines read so far.
+    lines: u64,
+    reader: Option<LineReader>,
+    buffer_size: usize,
+    /// The file now at `filename`, held until the old one is drained.
+    rotated: Option<(File, FileId, RotationKind)>,
+    rotation: RotationDetection,
+    /// Whether the deletion of the file being read has been reported.
+    deleted: bool,
+    /// Set when `filename` may point at another file than the owner
+    /// watches, until `take_reopened`.
+    reopened: bool,
+    /// Failures in a row, and when to try again.
+    backoff: Backoff,
+    /// When `filename` was found missing, and when that was last reported.
+    missing: Option<(Instant, Instant)>,
+    missing_timeout: Option<Duration>,
+    encoding: EncodingPolicy,
+    /// When the unterminated line left in the read buffer was first seen.
+    /// It stays there until its delimiter shows up.
+    partial: Option<Instant>,
+    flush_timeout: Option<Duration>,
+    delimiter: Delimiter,
+    line_limit: Option<LineLimit>,
+    /// Whether the rest of a truncated line is being skipped.
+    discarding: bool,
+    multiline: Option<Joiner>,
+    /// Lines handed out by reference that aren't in the read buffer:
+    /// multiline records and lossily decoded lines.
+    text: String,
+    /// Lines delivered as bytes while multiline is on, handed out by
+    /// reference.
+    bytes: Vec<u8>,
+    /// Nothing is delivered until then. A pause too long to have an end
+    /// lasts until the next seek.
+    paused_until: Option<Option<Instant>>,
+    /// Set once the follower has failed for good.
+    finished: bool,
+    /// A failure to deliver with the next event.
+    error: Option<LogWatcherError>,
+    /// An event to deliver before reading on.
+    queued: Option<LogWatcherEvent>,
+    /// Set once the file is being dropped. What is left of it is
+    /// delivered without holding anything back, and nothing else is
+    /// checked for.
+    closing: bool,
+}
+
+impl Follower {
+    /// Opens `filename` and moves to `start`, counting lines by
+    /// `delimiter` if it has to.
+    pub(crate) fn open(
+        filename: &Path,
+        start: StartPosition,
+        delimiter: &Delimiter,
+    ) -> Result<Follower, LogWatcherError> {
+        let mut f = File::open(filename).map_err(|e| LogWatcherError::open(filename, 0, e))?;
+        let metadata = f
+            .metadata()
+            .map_err(|e| LogWatcherError::read(filename, 0, e))?;
+
+        let pos = start
+            .offset(&mut f, metadata.len(), delimiter)
+            .map_err(|e| LogWatcherError::read(filename, 0, e))?;
+        let reader = reader_at(f, pos).map_err(|e| LogWatcherError::seek(filename, pos, e))?;
+        Ok(Follower::new(
+            filename,
+            Some(FileId::of(&metadata)),
+            pos,
+            Some(reader),
+        ))
+    }
+
+    /// Like `open`, but a missing file is waited for and read from the
+    /// start once it shows up, the same way a recreated file is picked up
+    /// after a rotation.
+    pub(crate) fn open_or_wait(
+        filename: &Path,
+        start: StartPosition,
+        delimiter: &Delimiter,
+    ) -> Result<Follower, LogWatcherError> {
+        match Follower::open(filename, start, delimiter) {
+            Err(LogWatcherError::Open { source, .. })
+                if source.kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(Follower::new(filename, None, 0, None))
+            }
+            result => result,
+        }
+    }
+
+    fn new(filename: &Path, id: Option<FileId>, pos: u64, reader: Option<LineReader>) -> Follower {
+        Follower {
+            filename: Arc::from(filename),
+            id,
+            pos,
+            lines: 0,
+            reader,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            rotated: None,
+            rotation: RotationDetection::default(),
+            deleted: false,
+            reopened: false,
+            backoff: Backoff::new(RetryPolicy::default()),
+            missing: None,
+            missing_timeout: None,
+            encoding: EncodingPolicy::default(),
+            partial: None,
+            flush_timeout: None,
+            delimiter: Delimiter::default(),
+            line_limit: None,
+            discarding: false,
+            multiline: None,
+            text: String::new(),
+            bytes: Vec::new(),
+            paused_until: None,
+            finished: false,
+            error: None,
+            queued: None,
+            closing: false,
+        }
+    }
+
+    /// Picks up where a previous watcher left off. If `filename` is still
+    /// the checkpointed file, reading continues at the saved offset. If it
+    /// has been rotated since, the old file is looked up in the same
+    /// directory and drained from the saved offset first, followed by a
+    /// rotation event and the new file from the start. If the old file is
+    /// gone, the new file is read from the start. A file truncated
+    /// meanwhile is read from the start after a `Truncated` rotation
+    /// event.
+    pub(crate) fn resume(
+        filename: &Path,
+        checkpoint: Checkpoint,
+    ) -> Result<Follower, LogWatcherError> {
+        let offset = checkpoint.offset;
+        let f = File::open(filename).map_err(|e| LogWatcherError::open(filename, offset, e))?;
+        let metadata = f
+            .metadata()
+            .map_err(|e| LogWatcherError::read(filename, offset, e))?;
+        let saved = FileId {
+            dev: checkpoint.dev,
+            ino: checkpoint.ino,
+        };
+        let current = FileId::of(&metadata);
+
+        if current == saved {
+            if offset <= metadata.len() {
+                let reader =
+                    reader_at(f, offset).map_err(|e| LogWatcherError::seek(filename, offset, e))?;
+                return Ok(Follower::new(filename, Some(current), offset, Some(reader)));
+            }
+            // A file shorter than the offset was truncated meanwhile, and
+            // is read again from the start.
+            let reader = LineReader::new(f, DEFAULT_BUFFER_SIZE);
+            let mut follower = Follower::new(filename, Some(current), 0, Some(reader));
+            follower.queued = Some(LogWatcherEvent::Rotation(Rotation {
+                kind: RotationKind::Truncated,
+                old: current,
+                new: Some(current),
+                offset,
+            }));
+            return Ok(follower);
+        }
+
+        match find_rotated(filename, saved) {
+            Some(old) => {
+                let old_metadata = old
+                    .metadata()
+                    .map_err(|e| LogWatcherError::rotation(filename, offset, e))?;
+                let kind = rotation::kind(filename, &old_metadata);
+                let pos = offset.min(old_metadata.len());
+                let reader =
+                    reader_at(old, pos).map_err(|e| LogWatcherError::seek(filename, pos, e))?;
+                let mut follower = Follower::new(filename, Some(saved), pos, Some(reader));
+                follower.rotated = Some((f, current, kind));
+                Ok(follower)
+            }
+            None => Ok(Follower::new(
+                filename,
+                Some(current),
+                0,
+                Some(LineReader::new(f, DEFAULT_BUFFER_SIZE)),
+            )),
+        }
+    }
+
+    /// Like `resume`, but a missing file is waited for. The checkpointed
+    /// file is drained first if it can still be found next to it.
+    pub(crate) fn resume_or_wait(
+        filename: &Path,
+        checkpoint: Checkpoint,
+    ) -> Result<Follower, LogWatcherError> {
+        match Follower::resume(filename, checkpoint) {
+            Err(LogWatcherError::Open { source, .. })
+                if source.kind() == io::ErrorKind::NotFound =>
+            {
+                let saved = FileId {
+                    dev: checkpoint.dev,
+                    ino: checkpoint.ino,
+                };
+                let old = match find_rotated(filename, saved) {
+                    Some(old) => old,
+                    None => return Ok(Follower::new(filename, None, 0, None)),
+                };
+                let offset = checkpoint.offset;
+                let len = old
+                    .metadata()
+                    .map_err(|e| LogWatcherError::rotation(filename, offset, e))?
+                    .len();
+                let pos = offset.min(len);
+                let reader =
+                    reader_at(old, pos).map_err(|e| LogWatcherError::seek(filename, pos, e))?;
+                Ok(Follower::new(filename, Some(saved), pos, Some(reader)))
+            }
+            result => result,
+        }
+    }
+
+    /// Where reading stands, unless there is no file yet. A multiline
+    /// record that hasn't been delivered yet is read again after a
+    /// restart.
+    pub(crate) fn checkpoint(&self) -> Option<Checkpoint> {
+        let offset = self.multiline.as_ref().and_then(Joiner::offset);
+        self.id.map(|id| Checkpoint {
+            dev: id.dev,
+            ino: id.ino,
+            offset: offset.unwrap_or(self.pos),
+        })
+    }
+
+    /// Changes how much is read from the file at a time.
+    pub(crate) fn set_buffer_size(&mut self, size: usize) {
+        // An empty buffer would never read anything.
+        self.buffer_size = size.max(1);
+        if let Some(reader) = &mut self.reader {
+            reader.set_capacity(self.buffer_size);
+        }
+    }
+
+    pub(crate) fn set_rotation_detection(&mut self, rotation: RotationDetection) {
+        self.rotation = rotation;
+    }
+
+    pub(crate) fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.backoff.set_policy(policy);
+    }
+
+    pub(crate) fn set_missing_timeout(&mut self, timeout: Option<Duration>) {
+        self.missing_timeout = timeout;
+    }
+
+    pub(crate) fn set_encoding(&mut self, encoding: EncodingPolicy) {
+        self.encoding = encoding;
+    }
+
+    pub(crate) fn set_flush_timeout(&mut self, timeout: Option<Duration>) {
+        self.flush_timeout = timeout;
+    }
+
+    pub(crate) fn set_delimiter(&mut self, delimiter: Delimiter) {
+        self.delimiter = delimiter;
+    }
+
+    pub(crate) fn set_line_limit(&mut self, limit: Option<LineLimit>) {
+        self.line_limit = limit;
+    }
+
+    /// Starts joining lines into records, or stops with `None`. A record
+    /// being put together is dropped.
+    pub(crate) fn set_multiline(&mut self, multiline: Option<Multiline>) {
+        self.multiline = multiline.map(Joiner::new);
+    }
+
+    /// When the follower wants to be asked again even if the file doesn't
+    /// change: to retry after a failure, to report a missing file, or to
+    /// flush an unterminated line or a multiline record, or once a pause
+    /// is over.
+    pub(crate) fn wake_at(&self) -> Option<Instant> {
+        if self.finished {
+            return None;
+        }
+        if let Some(until) = self.paused_until {
+            return until;
+        }
+        let missing = self.missing.map(|(since, reported)| {
+            let report = reported + MISSING_REPORT_INTERVAL;
+            match self.missing_timeout.and_then(|t| since.checked_add(t)) {
+                Some(timeout) => report.min(timeout),
+                None => report,
+            }
+        });
+        let flush = match (self.partial, self.flush_timeout) {
+            (Some(since), Some(timeout)) => since.checked_add(timeout),
+            _ => None,
+        };
+        let record = self.multiline.as_ref().and_then(Joiner::due_at);
+        [self.backoff.retry_at(), missing, flush, record]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Whether the follower has failed for good and that has been
+    /// reported: the retry policy ran out or the file stayed missing past
+    /// the timeout. It reports nothing more.
+    pub(crate) fn finished(&self) -> bool {
+        self.finished
+            && self.error.is_none()
+            && self.multiline.as_ref().is_none_or(Joiner::is_empty)
+    }
+
+    /// Whether the follower moved on to a new file, including the first
+    /// one showing up, or its missing file came back since last asked. The
+    /// owner moves its file watch over if so.
+    pub(crate) fn take_reopened(&mut self) -> bool {
+        std::mem::take(&mut self.reopened)
+    }
+
+    /// Gets ready for the file to be dropped: from now on, events are only
+    /// what is left to read from it, including an unterminated line and a
+    /// multiline record being put together.
+    pub(crate) fn close(&mut self) {
+        self.closing = true;
+        self.paused_until = None;
+    }
+
+    pub(crate) fn next_event(&mut self) -> Option<Result<LogWatcherEvent, LogWatcherError>> {
+        if self.paused() {
+            return None;
+        }
+        let mut joiner = match self.multiline.take() {
+            Some(joiner) => joiner,
+            None => return self.read_event(),
+        };
+        let event = self.join(&mut joiner);
+        self.multiline = Some(joiner);
+        event
+    }
+
+    /// Like `next_event`, but a line is lent out of the read buffer instead
+    /// of copied, until the next call.
+    pub(crate) fn next_event_ref(
+        &mut self,
+    ) -> Option<Result<LogWatcherEventRef<'_>, LogWatcherError>> {
+        if self.multiline.is_some() {
+            // Records are put together outside the read buffer anyway.
+            return match self.next_event()? {
+                Ok(LogWatcherEvent::Line(line, info)) => {
+                    self.text = line;
+                    Some(Ok(LogWatcherEventRef::Line(&self.text, info)))
+                }
+                Ok(LogWatcherEvent::Bytes(line, info)) => {
+                    self.bytes = line;
+                    Some(Ok(LogWatcherEventRef::Bytes(&self.bytes, info)))
+                }
+                event => Some(event.map(LogWatcherEventRef::Event)),
+            };
+        }
+        if self.paused() {
+            return None;
+        }
+        self.read_event_ref()
+    }
+
+    /// Whether a pause is still on.
+    fn paused(&mut self) -> bool {
+        match self.paused_until {
+            Some(Some(until)) if Instant::now() >= until => {
+                self.paused_until = None;
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Reads lines into multiline records.
+    fn join(&mut self, joiner: &mut Joiner) -> Option<Result<LogWatcherEvent, LogWatcherError>> {
+        if let Some(event) = joiner.take_queued() {
+            return Some(event);
+        }
+        loop {
+            match self.read_event() {
+                Some(Ok(LogWatcherEvent::Line(line, info))) => {
+                    if let Some(record) = joiner.push(line, info) {
+                        return Some(Ok(record));
+                    }
+                }
+                // Keep the record ahead of whatever came after it.
+                Some(event) => match joiner.flush() {
+                    Some(record) => {
+                        joiner.queue(event);
+                        return Some(Ok(record));
+                    }
+                    None => return Some(event),
+                },
+                None if self.closing => return joiner.flush().map(Ok),
+                None => return joiner.flush_due().map(Ok),
+            }
+        }
+    }
+
+    fn read_event(&mut self) -> Option<Result<LogWatcherEvent, LogWatcherError>> {
+        let event = match self.read()? {
+            Ok(Read::Line(line)) => {
+                let info = self.line_info(&line);
+                self.pos = info.end;
+                let bytes = take(&mut self.reader, &line).to_vec();
+                self.decode(bytes, info)
+            }
+            Ok(Read::Event(event)) => Ok(event),
+            Err(err) => Err(err),
+        };
+        Some(event)
+    }
+
+    fn read_event_ref(&mut self) -> Option<Result<LogWatcherEventRef<'_>, LogWatcherError>> {
+        let event = match self.read()? {
+            Ok(Read::Line(line)) => self.hand_out(line),
+            Ok(Read::Event(event)) => Ok(LogWatcherEventRef::Event(event)),
+            Err(err) => Err(err),
+        };
+        Some(event)
+    }
+
+    fn read(&mut self) -> Option<Result<Read, LogWatcherError>> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+        if let Some(event) = self.queued.take() {
+            return Some(Ok(Read::Event(event)));
+        }
+        if self.finished || !self.backoff.ready() {
+            return None;
+        }
+        // Enough for a line at the limit and its delimiter.
+        let limit = self.line_limit.map(|l| l.max() + self.delimiter.max_len());
+        loop {
+            let read = match self.reader.as_mut() {
+                Some(reader) => reader.next_line(&self.delimiter, limit),
+                None => Ok(None),
+            };
+            let len = match read {
+                Ok(len) => len,
+                Err(err) => {
+                    let err = LogWatcherError::read(&self.filename, self.pos, err);
+                    return Some(Err(self.fail(err)));
+                }
+            };
+            if let Some(len) = len {
+                if self.discarding {
+                    // More of a truncated line.
+                    let line = &self.buffered()[..len];
+                    self.discarding = self.delimiter.terminator_len(line) == 0;
+                    self.skip(len);
+                    continue;
+                }
+                self.partial = None;
+                match self.line(len) {
+                    Some(line) => return Some(Ok(Read::Line(line))),
+                    None => continue,
+                }
+            }
+            // At EOF, with an unterminated line left over if anything.
+            let held = self.buffered().len();
+            if held > 0 && self.discarding {
+                self.skip(held);
+            } else if held > 0 && (self.closing || self.hold()) {
+                self.partial = None;
+                match self.line(held) {
+                    Some(line) => return Some(Ok(Read::Line(line))),
+                    None => continue,
+                }
+            }
+
+            if self.closing {
+                return None;
+            }
+            // Nothing more is coming for an unterminated line in a file
+            // that has been replaced.
+            if self.rotated.is_some() {
+                if let Some(line) = self.flush_partial() {
+                    return Some(Ok(Read::Line(line)));
+                }
+            }
+            // Once the old file is drained, switch to the new one.
+            if let Some((f, id, kind)) = self.rotated.take() {
+                let old = self.id.replace(id);
+                let offset = std::mem::replace(&mut self.pos, 0);
+                self.reader = Some(LineReader::new(f, self.buffer_size));
+                self.deleted = false;
+                self.reopened = true;
+                match old {
+                    Some(old) => {
+                        let new = Some(id);
+                        let rotation = Rotation {
+                            kind,
+                            old,
+                            new,
+                            offset,
+                        };
+                        return Some(Ok(Read::Event(LogWatcherEvent::Rotation(rotation))));
+                    }
+                    // The file showed up for the first time.
+                    None => continue,
+                }
+            }
+            let metadata = self
+                .reader
+                .as_ref()
+                .and_then(|r| r.get_ref().metadata().ok());
+            // A file shorter than what has been read from it was truncated
+            // in place, which is how a `copytruncate` rotation shows up.
+            if let (Some(m), Some(id)) = (&metadata, self.id) {
+                let read = self.pos + self.buffered().len() as u64;
+                if self.rotation.truncation && m.len() < read {
+                    if let Some(line) = self.flush_partial() {
+                        return Some(Ok(Read::Line(line)));
+                    }
+                    if let Err(err) = self.seek(0) {
+                        return Some(Err(self.fail(err)));
+                    }
+                    let rotation = Rotation {
+                        kind: RotationKind::Truncated,
+                        old: id,
+                        new: Some(id),
+                        offset: self.pos,
+                    };
+                    self.pos = 0;
+                    return Some(Ok(Read::Event(LogWatcherEvent::Rotation(rotation))));
+                }
+            }
+            let reopen = match self.reopen_if_log_rotated(metadata.as_ref()) {
+                Ok(x) => x,
+                Err(err) => return Some(Err(self.fail(err))),
+            };
+            if reopen != Reopen::Missing {
+                if let Some((since, _)) = self.missing.take() {
+                    self.reopened = true;
+                    let after = since.elapsed();
+                    let event = LogWatcherEvent::FileReappeared { after };
+                    return Some(Ok(Read::Event(event)));
+                }
+            }
+            if reopen == Reopen::Rotated {
+                continue;
+            }
+            if let Some(old) = self.deleted(metadata.as_ref()) {
+                // Nothing more is coming for an unterminated line either.
+                if let Some(line) = self.flush_partial() {
+                    return Some(Ok(Read::Line(line)));
+                }
+                self.deleted = true;
+                let rotation = Rotation {
+                    kind: RotationKind::Deleted,
+                    old,
+                    new: None,
+                    offset: self.pos,
+                };
+                return Some(Ok(Read::Event(LogWatcherEvent::Rotation(rotation))));
+            }
+            if reopen == Reopen::Missing {
+                if let Some(event) = self.missing() {
+                    return Some(event.map(Read::Event));
+                }
+            }
+            self.backoff.succeeded();
+            return None;
+        }
+    }
+
+    /// Holds back the unterminated line in the read buffer until the flush
+    /// timeout has passed since it was first seen. Returns whether it has.
+    fn hold(&mut self) -> bool {
+        let since = *self.partial.get_or_insert_with(Instant::now);
+        self.flush_timeout.is_some_and(|t| since.elapsed() >= t)
+    }
+
+    /// Delivers the unterminated line being held back, if any.
+    fn flush_partial(&mut self) -> Option<PendingLine> {
+        self.partial.take()?;
+        let len = self.buffered().len();
+        self.line(len)
+    }
+
+    /// Sizes up the `len` bytes long line at the start of the read buffer.
+    /// A line the encoding policy skips is moved past right away, and
+    /// `None` returned.
+    fn line(&mut self, len: usize) -> Option<PendingLine> {
+        let line = &self.buffered()[..len];
+        let terminator = self.delimiter.terminator_len(line);
+        let limit = self.line_limit.filter(|l| len - terminator > l.max());
+        let (keep, consumed) = match limit {
+            Some(limit) => {
+                let cut = self.cut(line, limit.max());
+                match limit {
+                    LineLimit::Truncate(_) if terminator > 0 => (cut, len),
+                    // The rest is read again, as a line of its own or to
+                    // be skipped.
+                    _ => (cut, cut),
+                }
+            }
+            None => (len - terminator, len),
+        };
+        let skip =
+            self.encoding == EncodingPolicy::Skip && std::str::from_utf8(&line[..keep]).is_err();
+        self.lines += 1;
+        if let Some(LineLimit::Truncate(_)) = limit {
+            self.discarding = terminator == 0;
+        }
+        if skip {
+            self.consume(consumed);
+            return None;
+        }
+        Some(PendingLine {
+            len: consumed,
+            keep,
+            unterminated: terminator == 0 && limit.is_none(),
+            truncated: limit.is_some(),
+        })
+    }
+
+    /// Moves past a line and lends it out of the read buffer, decoded under
+    /// the encoding policy.
+    fn hand_out(&mut self, line: PendingLine) -> Result<LogWatcherEventRef<'_>, LogWatcherError> {
+        let info = self.line_info(&line);
+        self.pos = info.end;
+        let bytes = take(&mut self.reader, &line);
+        if self.encoding == EncodingPolicy::Raw {
+            return Ok(LogWatcherEventRef::Bytes(bytes, info));
+        }
+        match std::str::from_utf8(bytes) {
+            Ok(line) => Ok(LogWatcherEventRef::Line(line, info)),
+            Err(_) if self.encoding == EncodingPolicy::Lossy => {
+                self.text = String::from_utf8_lossy(bytes).into_owned();
+                Ok(LogWatcherEventRef::Line(&self.text, info))
+            }
+            // Lines to skip don't get this far.
+            Err(_) => Err(LogWatcherError::InvalidEncoding {
+                path: self.filename.to_path_buf(),
+                offset: info.offset,
+            }),
+        }
+    }
+
+    /// Turns a copy of a line into an event under the encoding policy.
+    fn decode(&self, line: Vec<u8>, info: LineInfo) -> Result<LogWatcherEvent, LogWatcherError> {
+        if self.encoding == EncodingPolicy::Raw {
+            return Ok(LogWatcherEvent::Bytes(line, info));
+        }
+        match String::from_utf8(line) {
+            Ok(line) => Ok(LogWatcherEvent::Line(line, info)),
+            Err(err) if self.encoding == EncodingPolicy::Lossy => {
+                let line = String::from_utf8_lossy(err.as_bytes()).into_owned();
+                Ok(LogWatcherEvent::Line(line, info))
+            }
+            // Lines to skip don't get this far.
+            Err(_) => Err(LogWatcherError::InvalidEncoding {
+                path: self.filename.to_path_buf(),
+                offset: info.offset,
+            }),
+        }
+    }
+
+    /// The `LineInfo` of a line at `pos`.
+    fn line_info(&self, line: &PendingLine) -> LineInfo {
+        LineInfo {
+            offset: self.pos,
+            end: self.pos + line.len as u64,
+            number: self.lines,
+            // There always is a file to read a line from.
+            file: self.id.unwrap_or_default(),
+            path: self.filename.clone(),
+            read_at: SystemTime::now(),
+            unterminated: line.unterminated,
+            truncated: line.truncated,
+        }
+    }
+
+    /// What has been read from the file but not delivered yet.
+    fn buffered(&self) -> &[u8] {
+        self.reader.as_ref().map_or(&[], LineReader::buffered)
+    }
+
+    /// Skips `len` bytes of a truncated line. Unless they end the line,
+    /// their end might be the start of a delimiter, which is kept to be
+    /// read again.
+    fn skip(&mut self, len: usize) {
+        let n = if self.discarding {
+            len.saturating_sub(self.delimiter.max_len() - 1)
+        } else {
+            len
+        };
+        self.consume(n);
+    }
+
+    /// Moves past the first `n` bytes of the read buffer.
+    fn consume(&mut self, n: usize) {
+        if let Some(reader) = &mut self.reader {
+            reader.consume(n);
+        }
+        self.pos += n as u64;
+    }
+
+    /// Where to cut a line longer than `max`: at `max`, or before a
+    /// character that would be cut in half there.
+    fn cut(&self, line: &[u8], max: usize) -> usize {
+        if self.encoding == EncodingPolicy::Raw {
+            return max;
+        }
+        // A UTF-8 character takes up to four bytes.
+        (max.saturating_sub(3)..=max)
+            .rev()
+            .find(|&i| i > 0 && line[i] & 0xc0 != 0x80)
+            .unwrap_or(max)
+    }
+
+    /// Seeks the file being read, if there is one, dropping whatever has
+    /// been read ahead.
+    fn seek(&mut self, pos: u64) -> Result<(), LogWatcherError> {
+        match self.reader.as_mut() {
+            Some(reader) => match reader.seek(pos) {
+                Ok(_) => Ok(()),
+                Err(err) => Err(LogWatcherError::seek(&self.filename, self.pos, err)),
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Backs off after a failure, or gives up if the retry policy says so,
+    /// in which case `LogWatcherError::GaveUp` follows `err`.
+    fn fail(&mut self, err: LogWatcherError) -> LogWatcherError {
+        if !self.backoff.failed() {
+            self.finished = true;
+            self.error = Some(LogWatcherError::GaveUp {
+                path: self.filename.to_path_buf(),
+                offset: self.pos,
+                attempts: self.backoff.attempts(),
+            });
+        }
+        err
+    }
+
+    /// Reports that `filename` is missing: when first noticed, then every
+    /// `MISSING_REPORT_INTERVAL`. Past the missing timeout the follower
+    /// fails for good.
+    fn missing(&mut self) -> Option<Result<LogWatcherEvent, LogWatcherError>> {
+        let now = Instant::now();
+        let (since, reported) = match self.missing {
+            Some(x) => x,
+            None => {
+                self.missing = Some((now, now));
+                let since = Duration::ZERO;
+                return Some(Ok(LogWatcherEvent::FileMissing { since }));
+            }
+        };
+        let since = now - since;
+        if self.missing_timeout.is_some_and(|t| since >= t) {
+            self.finished = true;
+            return Some(Err(LogWatcherError::FileVanished {
+                path: self.filename.to_path_buf(),
+                offset: self.pos,
+            }));
+        }
+        if now - reported < MISSING_REPORT_INTERVAL {
+            return None;
+        }
+        if let Some((_, reported)) = &mut self.missing {
+            *reported = now;
+        }
+        Some(Ok(LogWatcherEvent::FileMissing { since }))
+    }
+
+    /// Checks whether `filename` now points at a different file. If it does,
+    /// the new file is held in `rotated` until the old one, whose metadata
+    /// is `current`, is read to EOF. A missing file is not an error: it is
+    /// waited for.
+    fn reopen_if_log_rotated(
+        &mut self,
+        current: Option<&Metadata>,
+    ) -> Result<Reopen, LogWatcherError> {
+        // A file that didn't exist yet is opened regardless.
+        if !self.rotation.reopen && self.id.is_some() {
+            return Ok(Reopen::Unchanged);
+        }
+        let opened = File::open(&self.filename).and_then(|f| Ok((f.metadata()?, f)));
+        let (metadata, f) = match opened {
+            Ok(x) => x,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Reopen::Missing),
+            Err(err) => return Err(LogWatcherError::rotation(&self.filename, self.pos, err)),
+        };
+        if Some(FileId::of(&metadata)) == self.id {
+            return Ok(Reopen::Unchanged);
+        }
+        let kind = match current {
+            Some(current) => rotation::kind(&self.filename, current),
+            None => RotationKind::Renamed,
+        };
+        self.rotated = Some((f, FileId::of(&metadata), kind));
+        Ok(Reopen::Rotated)
+    }
+
+    /// Checks whether the file being read was deleted without anything
+    /// taking its place yet, and that hasn't been reported. Returns its
+    /// identity if so.
+    fn deleted(&self, current: Option<&Metadata>) -> Option<FileId> {
+        let old = self.id?;
+        if self.deleted || !self.rotation.reopen || current.is_none_or(|m| m.nlink() != 0) {
+            return None;
+        }
+        if fs::symlink_metadata(&self.filename).is_ok() {
+            return None;
+        }
+        Some(old)
+    }
+
+    /// Applies an action. A failure is delivered as the next event.
+    pub(crate) fn handle_action(&mut self, action: &LogWatcherAction) {
+        let offset = match action {
+            LogWatcherAction::SeekToStart => 0,
+            LogWatcherAction::SeekTo(offset) => *offset,
+            LogWatcherAction::SeekToEnd => u64::MAX,
+            LogWatcherAction::Pause(duration) => {
+                self.paused_until = Some(Instant::now().checked_add(*duration));
+                return;
+            }
+            LogWatcherAction::Stop | LogWatcherAction::SkipFile | LogWatcherAction::None => {
+                return;
+            }
+        };
+        if self.paused_until == Some(None) {
+            self.paused_until = None;
+        }
+        if let Err(err) = self.seek_to(offset) {
+            self.error = Some(err);
+        }
+    }
+
+    /// Moves reading to `offset`, or to the end of the file if that comes
+    /// first. Lines held back from before are dropped.
+    fn seek_to(&mut self, offset: u64) -> Result<(), LogWatcherError> {
+        self.partial = None;
+        self.discarding = false;
+        if let Some(joiner) = &mut self.multiline {
+            joiner.discard();
+        }
+        let len = match &self.reader {
+            Some(reader) => match reader.get_ref().metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(err) => return Err(LogWatcherError::read(&self.filename, self.pos, err)),
+            },
+            None => return Ok(()),
+        };
+        self.pos = offset.min(len);
+        self.seek(self.pos)
+    }
+}
+
+/// Moves `reader` past `line` and returns the part of it that is
+/// delivered, which stays in the read buffer until the next read.
+fn take<'a>(reader: &'a mut Option<LineReader>, line: &PendingLine) -> &'a [u8] {
+    match reader {
+        Some(reader) => &reader.take(line.len)[..line.keep],
+        None => &[],
+    }
+}
+
+/// A reader for `f` starting at `pos`.
+fn reader_at(mut f: File, pos: u64) -> io::Result<LineReader> {
+    f.seek(SeekFrom::Start(pos))?;
+    Ok(LineReader::new(f, DEFAULT_BUFFER_SIZE))
+}
+
+/// Looks for the file with identity `id` next to `filename`, where
+/// rotation usually moves it.
+fn find_rotated(filename: &Path, id: FileId) -> Option<File> {
+    let dir = match filename.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        match entry.metadata() {
+            Ok(m) if m.is_file() && FileId::of(&m) == id => return File::open(entry.path()).ok(),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn follower(file: &TempFile, delimiter: Delimiter, limit: Option<LineLimit>) -> Follower {
+        let mut follower =
+            Follower::open(file.path(), StartPosition::Beginning, &delimiter).unwrap();
+        follower.set_delimiter(delimiter);
+        follower.set_line_limit(limit);
+        follower
+    }
+
+    /// Everything there is to read, with truncated lines marked by a `~`.
+    fn lines(follower: &mut Follower) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Some(event) = follower.next_event() {
+            let (line, info) = match event.unwrap() {
+                LogWatcherEvent::Line(line, info) => (line, info),
+                LogWatcherEvent::Bytes(line, info) => (String::from_utf8(line).unwrap(), info),
+                _ => panic!("not a line"),
+            };
+            lines.push(if info.truncated { line + "~" } else { line });
+        }
+        lines
+    }
+
+    /// Everything there is to read, with rotations as their kind and the
+    /// offset reading stopped at.
+    fn events(follower: &mut Follower) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Some(event) = follower.next_event() {
+            events.push(match event.unwrap() {
+                LogWatcherEvent::Line(line, _) => line,
+                LogWatcherEvent::Rotation(r) => format!("{:?} at {}", r.kind, r.offset),
+                _ => panic!("neither a line nor a rotation"),
+            });
+        }
+        events
+    }
+
+    #[test]
+    fn delimiters_are_stripped() {
+        let file = TempFile::new(b"a\r\nb\nc\r\n");
+        let mut crlf = follower(&file, Delimiter::CrLf, None);
+        assert_eq!(lines(&mut crlf), ["a", "b", "c"]);
+        let mut newline = follower(&file, Delimiter::Newline, None);
+        assert_eq!(lines(&mut newline), ["a\r", "b", "c\r"]);
+    }
+
+    #[test]
+    fn truncate_cuts_before_a_multibyte_character() {
+        let file = TempFile::new("aé€b\nok\n".as_bytes());
+        let mut follower = follower(&file, Delimiter::Newline, Some(LineLimit::Truncate(4)));
+        assert_eq!(lines(&mut follower), ["aé~", "ok"]);
+    }
+
+    #[test]
+    fn split_cuts_before_a_multibyte_character() {
+        let file = TempFile::new("aé€b\nok\n".as_bytes());
+        let mut follower = follower(&file, Delimiter::Newline, Some(LineLimit::Split(4)));
+        assert_eq!(lines(&mut follower), ["aé~", "€b", "ok"]);
+    }
+
+    #[test]
+    fn raw_lines_are_cut_at_the_limit() {
+        let file = TempFile::new("aé€b\n".as_bytes());
+        let mut follower = follower(&file, Delimiter::Newline, Some(LineLimit::Split(2)));
+        follower.set_encoding(EncodingPolicy::Raw);
+        let mut cut = Vec::new();
+        while let Some(Ok(LogWatcherEvent::Bytes(line, _))) = follower.next_event() {
+            cut.push(line);
+        }
+        assert_eq!(cut.concat(), "aé€b".as_bytes());
+        assert!(cut.iter().all(|line| line.len() <= 2));
+    }
+
+    #[test]
+    fn discarding_goes_on_across_refills() {
+        let file = TempFile::new(b"0123456789abcdefghij\nnext\n");
+        let mut follower = follower(&file, Delimiter::Newline, Some(LineLimit::Truncate(5)));
+        follower.set_buffer_size(4);
+        assert_eq!(lines(&mut follower), ["01234~", "next"]);
+        assert_eq!(follower.pos, 26);
+    }
+
+    #[test]
+    fn discarding_finds_a_sequence_straddling_the_limit() {
+        let delimiter = Delimiter::Sequence(b"<EOR>".to_vec());
+        for len in 4..24 {
+            let mut contents = b"abcdefghijklmnopqrstuvwx"[..len].to_vec();
+            contents.extend_from_slice(b"<EOR>ok<EOR>");
+            let file = TempFile::new(&contents);
+            let limit = Some(LineLimit::Truncate(3));
+            let mut follower = follower(&file, delimiter.clone(), limit);
+            follower.set_buffer_size(4);
+            assert_eq!(lines(&mut follower), ["abc~", "ok"], "{len}");
+        }
+    }
+
+    #[test]
+    fn split_keeps_a_sequence_straddling_the_limit() {
+        let delimiter = Delimiter::Sequence(b"<EOR>".to_vec());
+        let file = TempFile::new(b"abcd<EOR>ef<EOR>");
+        let mut follower = follower(&file, delimiter, Some(LineLimit::Split(3)));
+        follower.set_buffer_size(4);
+        assert_eq!(lines(&mut follower), ["abc~", "d", "ef"]);
+    }
+
+    #[test]
+    fn partial_line_waits_for_its_delimiter() {
+        let file = TempFile::new(b"one\ntw");
+        let mut follower = follower(&file, Delimiter::Newline, None);
+        assert_eq!(lines(&mut follower), ["one"]);
+        let mut f = OpenOptions::new().append(true).open(file.path()).unwrap();
+        f.write_all(b"o\n").unwrap();
+        assert_eq!(lines(&mut follower), ["two"]);
+    }
+
+    #[test]
+    fn truncation_starts_over() {
+        let file = TempFile::new(b"one\ntwo\n");
+        let mut follower = follower(&file, Delimiter::Newline, None);
+        assert_eq!(events(&mut follower), ["one", "two"]);
+        fs::write(file.path(), b"new\n").unwrap();
+        assert_eq!(events(&mut follower), ["Truncated at 8", "new"]);
+        assert_eq!(follower.pos, 4);
+    }
+
+    #[test]
+    fn partial_line_comes_before_truncation() {
+        let file = TempFile::new(b"one\ntw");
+        let mut follower = follower(&file, Delimiter::Newline, None);
+        assert_eq!(events(&mut follower), ["one"]);
+        fs::write(file.path(), b"new\n").unwrap();
+        assert_eq!(events(&mut follower), ["tw", "Truncated at 6", "new"]);
+    }
+
+    #[test]
+    fn truncation_detection_can_be_turned_off() {
+        let file = TempFile::new(b"one\ntwo\n");
+        let mut follower = follower(&file, Delimiter::Newline, None);
+        follower.set_rotation_detection(RotationDetection {
+            truncation: false,
+            ..RotationDetection::default()
+        });
+        assert_eq!(events(&mut follower), ["one", "two"]);
+        fs::write(file.path(), b"new\n").unwrap();
+        assert_eq!(events(&mut follower), Vec::<String>::new());
+        assert_eq!(follower.pos, 8);
+    }
+}
@@ -1,133 +1,606 @@
-use std::fs::File;
-use std::io;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::io::ErrorKind;
-use std::io::SeekFrom;
-use std::os::unix::fs::MetadataExt;
-use std::path::Path;
-use std::thread::sleep;
-use std::time::Duration;
-
-pub use std::io::Error as LogWatcherError;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+mod batch;
+mod builder;
+mod checkpoint;
+mod delimiter;
+mod discover;
+mod encoding;
+mod error;
+mod follower;
+mod line;
+mod multiline;
+mod notify;
+mod reader;
+mod retry;
+mod rotation;
+mod set;
+mod start;
+mod stop;
+#[cfg(feature = "tokio")]
+mod stream;
+#[cfg(test)]
+mod testing;
+
+pub use batch::Batch;
+use batch::Collector;
+pub use builder::LogWatcherBuilder;
+use checkpoint::{Checkpoint, CheckpointFile};
+pub use delimiter::Delimiter;
+pub use discover::Discovery;
+pub use encoding::EncodingPolicy;
+pub use error::LogWatcherError;
+use follower::Follower;
+pub use line::{LineInfo, LineLimit};
+pub use multiline::Multiline;
+use notify::Notifier;
+pub use notify::WatchMode;
+pub use retry::RetryPolicy;
+pub use rotation::{FileId, Rotation, RotationDetection, RotationKind};
+pub use set::{LogWatcherSet, LogWatcherSetHandle};
+pub use start::StartPosition;
+pub use stop::{StopHandle, StopReason};
+#[cfg(feature = "tokio")]
+pub use stream::AsyncLogWatcher;
 
 pub enum LogWatcherEvent {
-    Line(String),
-    LogRotation,
+    /// A line without its delimiter.
+    Line(String, LineInfo),
+    /// A line as read from the file, without its delimiter, under
+    /// `EncodingPolicy::Raw`.
+    Bytes(Vec<u8>, LineInfo),
+    /// The file was rotated, truncated or deleted. Everything left in the
+    /// old file has been delivered before this event.
+    Rotation(Rotation),
+    /// The watched path no longer exists. Reported when first noticed and
+    /// then every minute while it stays missing, with how long ago it was
+    /// first noticed.
+    FileMissing { since: Duration },
+    /// The watched path exists again after `FileMissing`, `after` it was
+    /// first noticed missing.
+    FileReappeared { after: Duration },
+    /// A `LogWatcherSet` discovery found a matching file and started
+    /// following it.
+    FileDiscovered,
+    /// A discovered file left its directory and is no longer followed.
+    FileLost,
+}
+
+/// An event as passed to the `LogWatcher::watch_borrowed` callback. Lines
+/// are lent out of the read buffer and only live for the call.
+pub enum LogWatcherEventRef<'a> {
+    /// A line without its delimiter.
+    Line(&'a str, LineInfo),
+    /// A line without its delimiter under `EncodingPolicy::Raw`.
+    Bytes(&'a [u8], LineInfo),
+    /// Any other event.
+    Event(LogWatcherEvent),
+}
+
+impl LogWatcherEventRef<'_> {
+    /// Copies the line out, to keep it past the callback.
+    pub fn into_owned(self) -> LogWatcherEvent {
+        match self {
+            LogWatcherEventRef::Line(line, info) => LogWatcherEvent::Line(line.to_owned(), info),
+            LogWatcherEventRef::Bytes(line, info) => LogWatcherEvent::Bytes(line.to_vec(), info),
+            LogWatcherEventRef::Event(event) => event,
+        }
+    }
 }
 
 pub enum LogWatcherAction {
     None,
+    /// Read the file again from the beginning.
+    SeekToStart,
+    /// Continue reading at a byte offset, or at the end of the file if it
+    /// is shorter.
+    SeekTo(u64),
+    /// Skip everything written so far.
     SeekToEnd,
+    /// Deliver nothing from the file for a while. What is written in the
+    /// meantime is read once the pause is over. A pause too long to have
+    /// an end, such as `Duration::MAX`, lasts until the next seek.
+    Pause(Duration),
+    /// Stop following the file. A `LogWatcherSet` drops it like `remove`
+    /// and carries on with the other files; any other watcher stops.
+    SkipFile,
+    Stop,
 }
 
+/// Follows a log file, either through `watch` with a callback or as an
+/// iterator of events.
+///
+/// The iterator blocks until the next event arrives and ends once the
+/// watcher has been stopped.
 pub struct LogWatcher {
-    filename: String,
-    inode: u64,
-    pos: u64,
-    reader: BufReader<File>,
-    finish: bool,
+    follower: Follower,
+    notifier: Notifier,
+    stop_handle: StopHandle,
+    stopped: Option<StopReason>,
+    /// Whether the position has been saved since the watcher stopped, so
+    /// that a failure to is reported only once.
+    saved_on_stop: bool,
+    filename: PathBuf,
+    checkpoints: Option<CheckpointFile>,
+    /// While `watch_batched` holds back a batch, where reading stood
+    /// before it, if there was a file yet. That is what gets saved.
+    batch_start: Option<Option<Checkpoint>>,
 }
 
 impl LogWatcher {
-    pub fn register<P: AsRef<Path>>(filename: P) -> Result<LogWatcher, io::Error> {
-        let f = match File::open(&filename) {
-            Ok(x) => x,
-            Err(err) => return Err(err),
-        };
+    pub fn register<P: AsRef<Path>>(filename: P) -> Result<LogWatcher, LogWatcherError> {
+        LogWatcher::builder().register(filename)
+    }
 
-        let metadata = match f.metadata() {
-            Ok(x) => x,
-            Err(err) => return Err(err),
-        };
+    /// Configures a watcher before registering it.
+    pub fn builder() -> LogWatcherBuilder {
+        LogWatcherBuilder::new()
+    }
+
+    pub fn register_with_mode<P: AsRef<Path>>(
+        filename: P,
+        mode: WatchMode,
+    ) -> Result<LogWatcher, LogWatcherError> {
+        LogWatcher::builder().watch_mode(mode).register(filename)
+    }
+
+    /// Registers the file and starts reading at `start` instead of at its
+    /// current end.
+    pub fn register_at<P: AsRef<Path>>(
+        filename: P,
+        start: StartPosition,
+    ) -> Result<LogWatcher, LogWatcherError> {
+        LogWatcher::builder().start(start).register(filename)
+    }
 
-        let mut reader = BufReader::new(f);
-        let pos = metadata.len();
-        reader.seek(SeekFrom::Start(pos)).unwrap();
+    /// Registers a file that may not exist yet. If it is missing, its
+    /// directory is watched for it and the file is read from the start
+    /// once it appears, with `FileMissing` and `FileReappeared` around the
+    /// wait. A file that already exists is followed from its end, like
+    /// `register`.
+    pub fn register_allow_missing<P: AsRef<Path>>(
+        filename: P,
+    ) -> Result<LogWatcher, LogWatcherError> {
+        LogWatcher::builder().allow_missing(true).register(filename)
+    }
+
+    /// Registers the file and keeps how far it has been read in
+    /// `state_file`, so that a watcher registered later with the same state
+    /// file continues where this one left off, including lines written in
+    /// between. If the file was rotated meanwhile, the rest of the old file
+    /// is read first, provided it is still in the same directory. Without
+    /// a saved position, reading starts at the end of the file.
+    ///
+    /// A position counts as read once the next event has been asked for.
+    /// Positions are saved at most once a second while reading, shortly
+    /// after catching up, and when the watcher stops or is dropped. Lines
+    /// read after the last save are delivered again after a crash.
+    pub fn register_with_checkpoint<P: AsRef<Path>, Q: AsRef<Path>>(
+        filename: P,
+        state_file: Q,
+    ) -> Result<LogWatcher, LogWatcherError> {
+        LogWatcher::builder()
+            .checkpoint(state_file)
+            .register(filename)
+    }
+
+    fn open(
+        filename: &Path,
+        mode: WatchMode,
+        start: StartPosition,
+        checkpoints: Option<CheckpointFile>,
+        allow_missing: bool,
+        delimiter: &Delimiter,
+    ) -> Result<LogWatcher, LogWatcherError> {
+        // Set up the watch before reading the size so that no write between
+        // the two goes unnoticed.
+        let (stop_handle, wake) = StopHandle::new()?;
+        let mut notifier = Notifier::new(mode, wake);
+        notifier.add(0, filename);
+        let follower = match checkpoints.as_ref().and_then(|c| c.get(filename)) {
+            Some(checkpoint) if allow_missing => Follower::resume_or_wait(filename, checkpoint)?,
+            Some(checkpoint) => Follower::resume(filename, checkpoint)?,
+            None if allow_missing => Follower::open_or_wait(filename, start, delimiter)?,
+            None => Follower::open(filename, start, delimiter)?,
+        };
         Ok(LogWatcher {
-            filename: filename.as_ref().to_string_lossy().to_string(),
-            inode: metadata.ino(),
-            pos,
-            reader,
-            finish: false,
+            follower,
+            notifier,
+            stop_handle,
+            stopped: None,
+            saved_on_stop: false,
+            filename: filename.to_path_buf(),
+            checkpoints,
+            batch_start: None,
         })
     }
 
-    fn reopen_if_log_rotated<F: ?Sized>(&mut self, callback: &mut F) -> bool
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop_handle.clone()
+    }
+
+    /// Why the watcher stopped, or `None` while it is still running.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        if self.stopped.is_some() {
+            return self.stopped;
+        }
+        if self.stop_handle.is_stopped() {
+            return Some(StopReason::Handle);
+        }
+        if self.follower.finished() {
+            return Some(StopReason::Failed);
+        }
+        None
+    }
+
+    /// Changes how failures reading the file, or opening it after a
+    /// rotation, are retried.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.follower.set_retry_policy(policy);
+    }
+
+    /// Gives up on the file once it has been missing for `timeout`, with
+    /// `LogWatcherError::FileVanished`. By default a missing file is waited
+    /// for indefinitely.
+    pub fn set_missing_timeout(&mut self, timeout: Option<Duration>) {
+        self.follower.set_missing_timeout(timeout);
+    }
+
+    /// Changes what happens to lines that are not valid UTF-8. By default
+    /// each one is delivered as `LogWatcherError::InvalidEncoding`.
+    pub fn set_encoding(&mut self, encoding: EncodingPolicy) {
+        self.follower.set_encoding(encoding);
+    }
+
+    /// A line is only delivered once its delimiter has been written. With a
+    /// flush timeout, a line that has been waiting for its delimiter that
+    /// long is delivered as it is, flagged `LineInfo::unterminated`.
+    /// Without one it waits until the file is rotated or truncated.
+    pub fn set_flush_timeout(&mut self, timeout: Option<Duration>) {
+        self.follower.set_flush_timeout(timeout);
+    }
+
+    /// Changes what ends a line, `\n` by default.
+    pub fn set_delimiter(&mut self, delimiter: Delimiter) {
+        self.follower.set_delimiter(delimiter);
+    }
+
+    /// Caps how long a line can get, which is unlimited by default.
+    pub fn set_line_limit(&mut self, limit: Option<LineLimit>) {
+        self.follower.set_line_limit(limit);
+    }
+
+    /// Joins lines such as those of a stack trace into one event per
+    /// record, or stops joining with `None`.
+    pub fn set_multiline(&mut self, multiline: Option<Multiline>) {
+        self.follower.set_multiline(multiline);
+    }
+
+    /// Applies an action the way `watch` does with the callback's return
+    /// value. Use this when pulling events through the iterator.
+    pub fn handle_action(&mut self, action: LogWatcherAction) {
+        if let LogWatcherAction::Stop | LogWatcherAction::SkipFile = action {
+            self.stopped = Some(StopReason::Callback);
+        }
+        self.follower.handle_action(&action);
+    }
+
+    /// Waits at most `timeout` for the next event. Returns `None` if nothing
+    /// happened in time or the watcher has been stopped; `stop_reason` tells
+    /// the two apart.
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Option<Result<LogWatcherEvent, LogWatcherError>> {
+        // A timeout too long to represent is no timeout at all.
+        self.next_event(Instant::now().checked_add(timeout))
+    }
+
+    fn next_event(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Option<Result<LogWatcherEvent, LogWatcherError>> {
+        loop {
+            if self.stop_reason().is_some() {
+                if std::mem::replace(&mut self.saved_on_stop, true) {
+                    return None;
+                }
+                return self.save_checkpoint(true).err().map(Err);
+            }
+            self.saved_on_stop = false;
+            if let Err(err) = self.save_checkpoint(false) {
+                return Some(Err(err));
+            }
+            let event = self.follower.next_event();
+            if self.follower.take_reopened() {
+                self.notifier.rewatch(0);
+                // Writes to the new file that landed before the watch
+                // moved over raised no event, so read once more.
+                if event.is_none() {
+                    continue;
+                }
+            }
+            if let Some(event) = event {
+                return Some(event);
+            }
+            if !self.wait(deadline) {
+                return None;
+            }
+        }
+    }
+
+    /// Waits for the file to change, for something the follower wants to
+    /// be woken for, or until `deadline`. Returns `false` once the deadline
+    /// has passed.
+    fn wait(&mut self, deadline: Option<Instant>) -> bool {
+        let mut timeout = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(t) if !t.is_zero() => Some(t),
+                _ => return false,
+            },
+            None => None,
+        };
+        // Wake up in time to save the position we caught up at.
+        let checkpoint = self.checkpoint();
+        if let Some(checkpoints) = &mut self.checkpoints {
+            if let Some(checkpoint) = checkpoint {
+                checkpoints.set(&self.filename, checkpoint);
+            }
+            if let Some(due) = checkpoints.due_in() {
+                timeout = Some(timeout.map_or(due, |t| t.min(due)));
+            }
+        }
+        // Retry after a failure or report a missing file even if nothing
+        // changes.
+        if let Some(at) = self.follower.wake_at() {
+            let due = at.saturating_duration_since(Instant::now());
+            timeout = Some(timeout.map_or(due, |t| t.min(due)));
+        }
+        self.notifier.wait(timeout, &mut Vec::new());
+        true
+    }
+
+    /// Saves the position if checkpointing is on and either `now` is set
+    /// or the last save was long enough ago.
+    fn save_checkpoint(&mut self, now: bool) -> Result<(), LogWatcherError> {
+        let checkpoint = self.checkpoint();
+        match &mut self.checkpoints {
+            Some(checkpoints) if now || checkpoints.due() => {
+                if let Some(checkpoint) = checkpoint {
+                    checkpoints.set(&self.filename, checkpoint);
+                }
+                checkpoints
+                    .save()
+                    .map_err(|e| LogWatcherError::checkpoint(checkpoints.path(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Where reading stands as far as the state file is concerned.
+    fn checkpoint(&self) -> Option<Checkpoint> {
+        match self.batch_start {
+            Some(start) => start,
+            None => self.follower.checkpoint(),
+        }
+    }
+
+    /// Follows the file until the callback returns `LogWatcherAction::Stop`
+    /// or the stop handle fires. If the retry policy runs out or the file
+    /// stays missing past the missing timeout, the error that ended
+    /// watching is returned instead of passed to the callback.
+    pub fn watch<F>(&mut self, callback: &mut F) -> Result<StopReason, LogWatcherError>
     where
-        F: FnMut(Result<LogWatcherEvent, LogWatcherError>) -> LogWatcherAction,
+        F: ?Sized + FnMut(Result<LogWatcherEvent, LogWatcherError>) -> LogWatcherAction,
     {
+        self.stopped = None;
         loop {
-            match File::open(&self.filename) {
-                Ok(x) => {
-                    let f = x;
-                    let metadata = match f.metadata() {
-                        Ok(m) => m,
-                        Err(_) => {
-                            sleep(Duration::new(1, 0));
-                            continue;
-                        }
-                    };
-                    if metadata.ino() != self.inode {
-                        self.finish = true;
-                        self.watch(callback);
-                        self.finish = false;
-                        self.reader = BufReader::new(f);
-                        self.pos = 0;
-                        self.inode = metadata.ino();
-                        return true;
-                    } else {
-                        sleep(Duration::new(1, 0));
-                    }
-                    return false;
+            if let Some(reason) = self.stop_reason() {
+                if let Err(err) = self.save_checkpoint(true) {
+                    callback(Err(err));
                 }
-                Err(err) => {
-                    if err.kind() == ErrorKind::NotFound {
-                        sleep(Duration::new(1, 0));
-                        continue;
+                return Ok(reason);
+            }
+            match self.next_event(None) {
+                Some(Err(err)) if self.follower.finished() => {
+                    if let Err(err) = self.save_checkpoint(true) {
+                        callback(Err(err));
                     }
+                    return Err(err);
                 }
-            };
+                Some(event) => {
+                    let action = callback(event);
+                    self.handle_action(action);
+                }
+                None => {}
+            }
         }
     }
 
-    fn handle_callback_action(&mut self, action: LogWatcherAction) {
-        match action {
-            LogWatcherAction::SeekToEnd => {
-                self.reader.seek(SeekFrom::End(0)).unwrap();
+    /// Like `watch`, but the callback borrows each line from the read
+    /// buffer instead of getting a copy of its own, so that following a
+    /// file allocates nothing per line. A line only lives for the call;
+    /// `LogWatcherEventRef::into_owned` copies it out to keep it.
+    pub fn watch_borrowed<F>(&mut self, callback: &mut F) -> Result<StopReason, LogWatcherError>
+    where
+        F: ?Sized + FnMut(Result<LogWatcherEventRef<'_>, LogWatcherError>) -> LogWatcherAction,
+    {
+        self.stopped = None;
+        loop {
+            if let Some(reason) = self.stop_reason() {
+                if let Err(err) = self.save_checkpoint(true) {
+                    callback(Err(err));
+                }
+                return Ok(reason);
+            }
+            if let Err(err) = self.save_checkpoint(false) {
+                let action = callback(Err(err));
+                self.handle_action(action);
+                continue;
             }
-            LogWatcherAction::None => {}
+            // The line lent out borrows the follower, so the watch moves
+            // over before the next read rather than right after this one.
+            if self.follower.take_reopened() {
+                self.notifier.rewatch(0);
+            }
+            let event = match self.follower.next_event_ref() {
+                Some(event) => event,
+                None => {
+                    // Writes to the new file that landed before the watch
+                    // moved over raised no event, so read once more.
+                    if self.follower.take_reopened() {
+                        self.notifier.rewatch(0);
+                    } else {
+                        self.wait(None);
+                    }
+                    continue;
+                }
+            };
+            let action = match event {
+                Ok(event) => callback(Ok(event)),
+                Err(err) => {
+                    if self.follower.finished() {
+                        if let Err(err) = self.save_checkpoint(true) {
+                            callback(Err(err));
+                        }
+                        return Err(err);
+                    }
+                    callback(Err(err))
+                }
+            };
+            self.handle_action(action);
         }
     }
 
-    pub fn watch<F: ?Sized>(&mut self, callback: &mut F)
+    /// Like `watch`, but hands events to the callback in batches, as
+    /// `batch` sets out, and applies the action it returns to the whole
+    /// batch. Events are in the order they happened, lines along with
+    /// rotations and errors. Whatever has been collected when the watcher
+    /// stops or fails is handed over first; the action returned for that
+    /// last batch is ignored.
+    ///
+    /// With a checkpoint, a batch counts as read once the callback has
+    /// returned, so a batch that was never handed over is read again after
+    /// a restart.
+    pub fn watch_batched<F>(
+        &mut self,
+        batch: Batch,
+        callback: &mut F,
+    ) -> Result<StopReason, LogWatcherError>
     where
-        F: FnMut(Result<LogWatcherEvent, LogWatcherError>) -> LogWatcherAction,
+        F: ?Sized + FnMut(Vec<Result<LogWatcherEvent, LogWatcherError>>) -> LogWatcherAction,
     {
+        self.stopped = None;
+        let mut collector = Collector::new(batch);
         loop {
-            let mut line = String::new();
-            let resp = self.reader.read_line(&mut line);
-            match resp {
-                Ok(len) => {
-                    if len > 0 {
-                        self.pos += len as u64;
-                        self.reader.seek(SeekFrom::Start(self.pos)).unwrap();
-                        let event = LogWatcherEvent::Line(line.replace('\n', ""));
-                        self.handle_callback_action(callback(Ok(event)));
-                        line.clear();
-                    } else if self.finish {
-                        break;
-                    } else {
-                        if self.reopen_if_log_rotated(callback) {
-                            self.handle_callback_action(callback(Ok(LogWatcherEvent::LogRotation)));
-                        }
-                        self.reader.seek(SeekFrom::Start(self.pos)).unwrap();
+            if collector.full() {
+                let action = callback(collector.take());
+                self.batch_start = None;
+                self.handle_action(action);
+            }
+            if let Some(reason) = self.stop_reason() {
+                if !collector.is_empty() {
+                    callback(collector.take());
+                    self.batch_start = None;
+                }
+                if let Err(err) = self.save_checkpoint(true) {
+                    callback(vec![Err(err)]);
+                }
+                return Ok(reason);
+            }
+            let start = self.follower.checkpoint();
+            match self.next_event(collector.due_at()) {
+                Some(Err(err)) if self.follower.finished() => {
+                    if !collector.is_empty() {
+                        callback(collector.take());
+                        self.batch_start = None;
+                    }
+                    if let Err(err) = self.save_checkpoint(true) {
+                        callback(vec![Err(err)]);
                     }
+                    return Err(err);
                 }
-                Err(err) => {
-                    self.handle_callback_action(callback(Err(err)));
+                Some(event) => {
+                    collector.push(event, start);
+                    self.batch_start = Some(collector.start());
                 }
+                None => {}
+            }
+        }
+    }
+}
+
+impl Drop for LogWatcher {
+    fn drop(&mut self) {
+        // Nobody is left to tell about a failure.
+        let _ = self.save_checkpoint(true);
+    }
+}
+
+impl Iterator for LogWatcher {
+    type Item = Result<LogWatcherEvent, LogWatcherError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::thread;
+
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn next_line(watcher: &mut LogWatcher) -> Option<String> {
+        loop {
+            match watcher.next_timeout(Duration::from_secs(2))? {
+                Ok(LogWatcherEvent::Line(line, _)) => return Some(line),
+                Ok(_) => {}
+                Err(err) => panic!("{err}"),
             }
         }
     }
+
+    #[test]
+    fn file_showing_up_is_watched() {
+        let file = TempFile::new(b"");
+        fs::remove_file(file.path()).unwrap();
+        let mut watcher = LogWatcher::register_allow_missing(file.path()).unwrap();
+        fs::write(file.path(), b"one\n").unwrap();
+        assert_eq!(next_line(&mut watcher).as_deref(), Some("one"));
+        // Use up the event for the file's creation.
+        assert!(watcher.next_timeout(Duration::from_millis(100)).is_none());
+        // Written while the watcher waits.
+        let path = file.path().to_path_buf();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let mut f = OpenOptions::new().append(true).open(path).unwrap();
+            f.write_all(b"two\n").unwrap();
+        });
+        let waited = Instant::now();
+        assert_eq!(next_line(&mut watcher).as_deref(), Some("two"));
+        // Not just read once the timeout ran out.
+        assert!(waited.elapsed() < Duration::from_secs(1));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn failed_save_on_stop_is_reported_once() {
+        let file = TempFile::new(b"one\n");
+        let mut watcher = LogWatcher::builder()
+            .checkpoint("/nonexistent-dir/state")
+            .register(file.path())
+            .unwrap();
+        watcher.stop_handle().stop();
+        assert!(matches!(
+            watcher.next(),
+            Some(Err(LogWatcherError::Checkpoint { .. }))
+        ));
+        assert!(watcher.next().is_none());
+    }
 }
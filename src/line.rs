@@ -0,0 +1,55 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::FileId;
+
+/// Details about a line, delivered with `LogWatcherEvent::Line` and
+/// `LogWatcherEvent::Bytes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineInfo {
+    /// Where the line starts in the file.
+    pub offset: u64,
+    /// Where the line ends in the file, after its delimiter. For a
+    /// truncated line, where reading it stopped.
+    pub end: u64,
+    /// How many lines had been read before this one since the watcher was
+    /// registered, plus one. It is not reset by rotations or seeks, so it
+    /// only matches the position in the file when reading started at the
+    /// beginning and never moved.
+    pub number: u64,
+    /// The file the line was read from.
+    pub file: FileId,
+    /// The path the file was followed by.
+    pub path: Arc<Path>,
+    /// When the line was read.
+    pub read_at: SystemTime,
+    /// The line was delivered before its delimiter was written: it waited
+    /// out the flush timeout, or the file was rotated or truncated first.
+    /// Whatever the writer adds to it later arrives as a line of its own.
+    pub unterminated: bool,
+    /// The line was longer than the `LineLimit` and has been cut off
+    /// there. With `LineLimit::Split` the rest follows as the next line.
+    pub truncated: bool,
+}
+
+/// Caps how long a line can get, so that a writer that never writes a
+/// delimiter can't use up all memory. The limit is in bytes and doesn't
+/// count the delimiter. Unless lines are delivered as raw bytes, a line is
+/// cut before a character that would otherwise be cut in half.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineLimit {
+    /// Deliver the start of a longer line and skip the rest of it as it is
+    /// read, without holding on to it.
+    Truncate(usize),
+    /// Deliver a longer line in pieces.
+    Split(usize),
+}
+
+impl LineLimit {
+    pub(crate) fn max(self) -> usize {
+        match self {
+            LineLimit::Truncate(max) | LineLimit::Split(max) => max.max(1),
+        }
+    }
+}
@@ -14,15 +14,26 @@ fn main() {
 
     let mut log_watcher = LogWatcher::register(filename).unwrap();
 
-    log_watcher.watch(&mut move |result| {
+    let result = log_watcher.watch(&mut move |result| {
         match result {
             Ok(event) => match event {
-                LogWatcherEvent::Line(line) => {
+                LogWatcherEvent::Line(line, _) => {
                     println!("Line {}", line);
                 }
-                LogWatcherEvent::LogRotation => {
-                    println!("Logfile rotation");
+                LogWatcherEvent::Bytes(line, _) => {
+                    println!("Line {}", String::from_utf8_lossy(&line));
                 }
+                LogWatcherEvent::Rotation(rotation) => {
+                    println!("Logfile rotation {:?}", rotation.kind);
+                }
+                LogWatcherEvent::FileMissing { since } => {
+                    println!("Logfile missing for {:?}", since);
+                }
+                LogWatcherEvent::FileReappeared { .. } => {
+                    println!("Logfile reappeared");
+                }
+                // Only a LogWatcherSet discovers files.
+                LogWatcherEvent::FileDiscovered | LogWatcherEvent::FileLost => {}
             },
             Err(err) => {
                 println!("Error {}", err);
@@ -30,4 +41,8 @@ fn main() {
         }
         LogWatcherAction::None
     });
+    if let Err(err) = result {
+        println!("Error {}", err);
+        exit(1);
+    }
 }
@@ -0,0 +1,339 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use crate::{LineInfo, LogWatcherError, LogWatcherEvent};
+
+/// Joins lines that belong together, such as the lines of a stack trace,
+/// into one record delivered as a single `LogWatcherEvent::Line`.
+///
+/// A line is added to the record before it if it matches a continuation
+/// pattern, or if there is a start pattern and it doesn't match that.
+/// Every other line starts a new record. Lines in a record are joined
+/// with `\n`, and its `LineInfo` is that of the first line, apart from
+/// where it ends.
+///
+/// A record is delivered once a line comes that doesn't belong to it or
+/// would take it past the maximum number of lines, once no line has been
+/// added to it for the timeout, or right before any other event. Lines
+/// delivered as `LogWatcherEvent::Bytes` are not joined.
+#[derive(Debug, Clone)]
+pub struct Multiline {
+    start: Option<Regex>,
+    continuation: Vec<Regex>,
+    max_lines: usize,
+    timeout: Duration,
+}
+
+impl Multiline {
+    /// No patterns yet, at most 1000 lines per record and a timeout of one
+    /// second.
+    pub fn new() -> Multiline {
+        Multiline {
+            start: None,
+            continuation: Vec::new(),
+            max_lines: 1000,
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Records start with lines matching `pattern`, such as a timestamp
+    /// with `^\d{4}-\d{2}-\d{2}`.
+    pub fn start(mut self, pattern: &str) -> Result<Multiline, io::Error> {
+        self.start = Some(compile(pattern)?);
+        Ok(self)
+    }
+
+    /// Lines matching `pattern`, such as `^\s` for indented lines or
+    /// `^Caused by:`, continue the record before them.
+    pub fn continuation(mut self, pattern: &str) -> Result<Multiline, io::Error> {
+        self.continuation.push(compile(pattern)?);
+        Ok(self)
+    }
+
+    pub fn max_lines(mut self, max_lines: usize) -> Multiline {
+        self.max_lines = max_lines.max(1);
+        self
+    }
+
+    /// How long a record waits for more lines before it is delivered.
+    pub fn timeout(mut self, timeout: Duration) -> Multiline {
+        self.timeout = timeout;
+        self
+    }
+
+    fn continues(&self, line: &str) -> bool {
+        self.continuation.iter().any(|p| p.is_match(line))
+            || self.start.as_ref().is_some_and(|p| !p.is_match(line))
+    }
+}
+
+impl Default for Multiline {
+    fn default() -> Multiline {
+        Multiline::new()
+    }
+}
+
+/// A record being put together.
+struct Record {
+    text: String,
+    info: LineInfo,
+    lines: usize,
+    /// When the last line was added.
+    updated: Instant,
+}
+
+/// Puts records together from the lines a follower reads.
+pub(crate) struct Joiner {
+    config: Multiline,
+    record: Option<Record>,
+    /// The event that ended the record delivered last, to deliver next.
+    queued: Option<Result<LogWatcherEvent, LogWatcherError>>,
+}
+
+impl Joiner {
+    pub(crate) fn new(config: Multiline) -> Joiner {
+        Joiner {
+            config,
+            record: None,
+            queued: None,
+        }
+    }
+
+    /// Adds a line. Returns the record before it if the line doesn't
+    /// belong to it, or if the record is full.
+    pub(crate) fn push(&mut self, line: String, info: LineInfo) -> Option<LogWatcherEvent> {
+        let max_lines = self.config.max_lines;
+        match &mut self.record {
+            Some(record) if record.lines < max_lines && self.config.continues(&line) => {
+                record.text.push('\n');
+                record.text.push_str(&line);
+                record.info.end = info.end;
+                record.info.unterminated = info.unterminated;
+                record.lines += 1;
+                record.updated = Instant::now();
+                None
+            }
+            _ => {
+                let record = Record {
+                    text: line,
+                    info,
+                    lines: 1,
+                    updated: Instant::now(),
+                };
+                self.record.replace(record).map(Record::into_event)
+            }
+        }
+    }
+
+    /// Hands over the record being put together, if any.
+    pub(crate) fn flush(&mut self) -> Option<LogWatcherEvent> {
+        self.record.take().map(Record::into_event)
+    }
+
+    /// Drops the record being put together.
+    pub(crate) fn discard(&mut self) {
+        self.record = None;
+    }
+
+    /// Hands over the record if it has waited out the timeout.
+    pub(crate) fn flush_due(&mut self) -> Option<LogWatcherEvent> {
+        match &self.record {
+            Some(record) if record.updated.elapsed() >= self.config.timeout => self.flush(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn queue(&mut self, event: Result<LogWatcherEvent, LogWatcherError>) {
+        self.queued = Some(event);
+    }
+
+    pub(crate) fn take_queued(&mut self) -> Option<Result<LogWatcherEvent, LogWatcherError>> {
+        self.queued.take()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.record.is_none() && self.queued.is_none()
+    }
+
+    /// Where the record being put together starts.
+    pub(crate) fn offset(&self) -> Option<u64> {
+        self.record.as_ref().map(|r| r.info.offset)
+    }
+
+    /// When the record being put together is due, unless never: without
+    /// a record, or with a timeout too long to have an end.
+    pub(crate) fn due_at(&self) -> Option<Instant> {
+        self.record
+            .as_ref()
+            .and_then(|r| r.updated.checked_add(self.config.timeout))
+    }
+}
+
+impl Record {
+    fn into_event(self) -> LogWatcherEvent {
+        LogWatcherEvent::Line(self.text, self.info)
+    }
+}
+
+fn compile(pattern: &str) -> io::Result<Regex> {
+    Regex::new(pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::FileId;
+
+    /// A line that starts at `offset` and ends with a newline.
+    fn line(text: &str, offset: u64) -> (String, LineInfo) {
+        let info = LineInfo {
+            offset,
+            end: offset + text.len() as u64 + 1,
+            number: 0,
+            file: FileId::default(),
+            path: Arc::from(Path::new("app.log")),
+            read_at: SystemTime::now(),
+            unterminated: false,
+            truncated: false,
+        };
+        (text.to_string(), info)
+    }
+
+    /// Pushes `lines` one after another, and returns the records that came
+    /// out followed by what is still being put together.
+    fn join(multiline: Multiline, lines: &[&str]) -> Vec<String> {
+        let mut joiner = Joiner::new(multiline);
+        let mut offset = 0;
+        let mut records = Vec::new();
+        for text in lines {
+            let (text, info) = line(text, offset);
+            offset = info.end;
+            records.extend(joiner.push(text, info).map(record));
+        }
+        records.extend(joiner.flush().map(record));
+        records
+    }
+
+    fn record(event: LogWatcherEvent) -> String {
+        match event {
+            LogWatcherEvent::Line(text, _) => text,
+            _ => panic!("not a record"),
+        }
+    }
+
+    #[test]
+    fn lines_not_matching_the_start_pattern_continue() {
+        let multiline = Multiline::new().start(r"^\d{4}-").unwrap();
+        let lines = ["2024-01 a", "trace 1", "trace 2", "2024-02 b", "2024-03 c"];
+        assert_eq!(
+            join(multiline, &lines),
+            ["2024-01 a\ntrace 1\ntrace 2", "2024-02 b", "2024-03 c"]
+        );
+    }
+
+    #[test]
+    fn lines_matching_a_continuation_pattern_continue() {
+        let multiline = Multiline::new()
+            .continuation(r"^\s")
+            .unwrap()
+            .continuation("^Caused by:")
+            .unwrap();
+        let lines = ["error", "  at a", "Caused by: b", "  at c", "next", "last"];
+        assert_eq!(
+            join(multiline, &lines),
+            ["error\n  at a\nCaused by: b\n  at c", "next", "last"]
+        );
+    }
+
+    #[test]
+    fn without_patterns_every_line_is_a_record() {
+        assert_eq!(join(Multiline::new(), &["a", " b"]), ["a", " b"]);
+    }
+
+    #[test]
+    fn full_record_is_delivered() {
+        let multiline = Multiline::new().continuation(r"^\s").unwrap().max_lines(2);
+        let lines = ["a", " 1", " 2", " 3", "b"];
+        assert_eq!(join(multiline, &lines), ["a\n 1", " 2\n 3", "b"]);
+    }
+
+    #[test]
+    fn record_spans_its_lines() {
+        let multiline = Multiline::new().continuation(r"^\s").unwrap();
+        let mut joiner = Joiner::new(multiline);
+        let (text, info) = line("b", 10);
+        assert!(joiner.push(text, info).is_none());
+        let (text, info) = line(" c", 12);
+        assert!(joiner.push(text, info).is_none());
+        assert_eq!(joiner.offset(), Some(10));
+        match joiner.flush() {
+            Some(LogWatcherEvent::Line(text, info)) => {
+                assert_eq!(text, "b\n c");
+                assert_eq!((info.offset, info.end), (10, 15));
+            }
+            _ => panic!("no record"),
+        }
+        assert_eq!(joiner.offset(), None);
+    }
+
+    #[test]
+    fn record_is_due_after_the_timeout() {
+        let mut joiner = Joiner::new(Multiline::new().timeout(Duration::ZERO));
+        assert!(joiner.flush_due().is_none());
+        let (text, info) = line("a", 0);
+        joiner.push(text, info);
+        assert!(joiner.due_at().is_some());
+        assert_eq!(joiner.flush_due().map(record).as_deref(), Some("a"));
+        assert!(joiner.is_empty());
+
+        let mut joiner = Joiner::new(Multiline::new().timeout(Duration::from_secs(3600)));
+        let (text, info) = line("a", 0);
+        joiner.push(text, info);
+        assert!(joiner.flush_due().is_none());
+        assert!(!joiner.is_empty());
+    }
+
+    #[test]
+    fn record_waits_for_good_without_a_timeout() {
+        let mut joiner = Joiner::new(Multiline::new().timeout(Duration::MAX));
+        let (text, info) = line("a", 0);
+        joiner.push(text, info);
+        assert_eq!(joiner.due_at(), None);
+        assert!(joiner.flush_due().is_none());
+    }
+
+    #[test]
+    fn queued_event_waits_behind_the_record() {
+        let mut joiner = Joiner::new(Multiline::new());
+        let (text, info) = line("a", 0);
+        joiner.push(text, info);
+        let missing = LogWatcherEvent::FileMissing {
+            since: Duration::ZERO,
+        };
+        assert_eq!(joiner.flush().map(record).as_deref(), Some("a"));
+        joiner.queue(Ok(missing));
+        assert!(!joiner.is_empty());
+        assert!(matches!(
+            joiner.take_queued(),
+            Some(Ok(LogWatcherEvent::FileMissing { .. }))
+        ));
+        assert!(joiner.take_queued().is_none());
+        assert!(joiner.is_empty());
+    }
+
+    #[test]
+    fn discarded_record_is_gone() {
+        let mut joiner = Joiner::new(Multiline::new());
+        let (text, info) = line("a", 0);
+        joiner.push(text, info);
+        joiner.discard();
+        assert!(joiner.flush().is_none());
+        assert!(joiner.is_empty());
+    }
+}
@@ -0,0 +1,476 @@
+use std::io;
+use std::io::Read;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// How the watcher learns that the log file has changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WatchMode {
+    /// Wake up on inotify events. Falls back to `Poll` with the default
+    /// interval when inotify is unavailable.
+    #[default]
+    Inotify,
+    /// Re-check the file every interval. Use this for filesystems where
+    /// inotify does not fire, such as NFS or some FUSE mounts.
+    Poll(Duration),
+}
+
+pub(crate) const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Waits for changes to any number of files, each known by a caller-chosen
+/// id.
+pub(crate) struct Notifier {
+    backend: Backend,
+    wake: UnixStream,
+    ids: Vec<usize>,
+    /// Files and directories inotify couldn't watch, such as those in a
+    /// directory that doesn't exist (yet). They are polled instead.
+    polled: Vec<(usize, PathBuf)>,
+}
+
+enum Backend {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::Inotify),
+    Poll(Duration),
+}
+
+impl Notifier {
+    pub(crate) fn new(mode: WatchMode, wake: UnixStream) -> Notifier {
+        let backend = match mode {
+            #[cfg(target_os = "linux")]
+            WatchMode::Inotify => match inotify::Inotify::new() {
+                Ok(x) => Backend::Inotify(x),
+                Err(_) => Backend::Poll(DEFAULT_POLL_INTERVAL),
+            },
+            #[cfg(not(target_os = "linux"))]
+            WatchMode::Inotify => Backend::Poll(DEFAULT_POLL_INTERVAL),
+            WatchMode::Poll(interval) => Backend::Poll(interval),
+        };
+        Notifier {
+            backend,
+            wake,
+            ids: Vec::new(),
+            polled: Vec::new(),
+        }
+    }
+
+    /// Starts watching `filename`. Set up the watch before reading the file
+    /// so that no write in between goes unnoticed.
+    pub(crate) fn add(&mut self, id: usize, filename: &Path) {
+        self.ids.push(id);
+        #[cfg(target_os = "linux")]
+        if let Backend::Inotify(x) = &mut self.backend {
+            if x.add(id, filename).is_err() {
+                self.polled.push((id, filename.to_path_buf()));
+            }
+        }
+    }
+
+    /// Starts watching the entries of `dir`: any file created in, moved
+    /// into, moved out of or deleted from it wakes `id`.
+    pub(crate) fn add_dir(&mut self, id: usize, dir: &Path) {
+        self.ids.push(id);
+        #[cfg(target_os = "linux")]
+        if let Backend::Inotify(x) = &mut self.backend {
+            if x.add_dir(id, dir).is_err() {
+                self.polled.push((id, dir.to_path_buf()));
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) {
+        self.ids.retain(|&x| x != id);
+        self.polled.retain(|&(x, _)| x != id);
+        #[cfg(target_os = "linux")]
+        if let Backend::Inotify(x) = &mut self.backend {
+            x.remove(id);
+        }
+    }
+
+    /// Blocks until something may have happened to one of the watched
+    /// files, the stop handle fires or `timeout` runs out. The ids of the
+    /// files that may have changed are appended to `ready`.
+    pub(crate) fn wait(&mut self, timeout: Option<Duration>, ready: &mut Vec<usize>) {
+        let wake = self.wake.as_raw_fd();
+        match &mut self.backend {
+            #[cfg(target_os = "linux")]
+            Backend::Inotify(x) => {
+                let timeout = if self.polled.is_empty() {
+                    timeout
+                } else {
+                    Some(timeout.map_or(DEFAULT_POLL_INTERVAL, |t| t.min(DEFAULT_POLL_INTERVAL)))
+                };
+                if x.wait(wake, timeout, ready).is_err() {
+                    self.backend = Backend::Poll(DEFAULT_POLL_INTERVAL);
+                    ready.extend_from_slice(&self.ids);
+                }
+                for (id, _) in &self.polled {
+                    if !ready.contains(id) {
+                        ready.push(*id);
+                    }
+                }
+            }
+            Backend::Poll(interval) => {
+                let interval = match timeout {
+                    Some(t) => t.min(*interval),
+                    None => *interval,
+                };
+                let mut fds = [pollfd(wake)];
+                if poll(&mut fds, Some(interval)).is_err() {
+                    sleep(interval);
+                }
+                ready.extend_from_slice(&self.ids);
+            }
+        }
+        self.drain_wake();
+    }
+
+    fn drain_wake(&mut self) {
+        let mut buf = [0u8; 64];
+        while let Ok(n) = self.wake.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Moves the file watch to whatever inode the path points at now.
+    /// Called after the log file has been reopened. A file that is being
+    /// polled is given another try with inotify.
+    pub(crate) fn rewatch(&mut self, id: usize) {
+        #[cfg(target_os = "linux")]
+        if let Backend::Inotify(x) = &mut self.backend {
+            match self.polled.iter().position(|&(x, _)| x == id) {
+                Some(i) => {
+                    if x.add(id, &self.polled[i].1).is_ok() {
+                        self.polled.remove(i);
+                    }
+                }
+                None => x.rewatch(id),
+            }
+        }
+    }
+}
+
+/// Async counterpart of `Notifier` for a single file: inotify readiness
+/// goes through the tokio reactor and polling mode uses tokio timers.
+#[cfg(feature = "tokio")]
+pub(crate) struct AsyncNotifier {
+    backend: AsyncBackend,
+}
+
+#[cfg(feature = "tokio")]
+enum AsyncBackend {
+    #[cfg(target_os = "linux")]
+    Inotify(tokio::io::unix::AsyncFd<inotify::Inotify>),
+    Poll(Duration, Option<std::pin::Pin<Box<tokio::time::Sleep>>>),
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncNotifier {
+    /// Must be called from within a tokio runtime.
+    pub(crate) fn new(filename: &Path, mode: WatchMode) -> AsyncNotifier {
+        let backend = match mode {
+            #[cfg(target_os = "linux")]
+            WatchMode::Inotify => {
+                let inotify = inotify::Inotify::new().and_then(|mut x| {
+                    x.add(0, filename)?;
+                    tokio::io::unix::AsyncFd::new(x)
+                });
+                match inotify {
+                    Ok(x) => AsyncBackend::Inotify(x),
+                    Err(_) => AsyncBackend::Poll(DEFAULT_POLL_INTERVAL, None),
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            WatchMode::Inotify => AsyncBackend::Poll(DEFAULT_POLL_INTERVAL, None),
+            WatchMode::Poll(interval) => AsyncBackend::Poll(interval, None),
+        };
+        AsyncNotifier { backend }
+    }
+
+    /// Resolves once something may have happened to the watched file.
+    pub(crate) fn poll_wait(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<()> {
+        use std::future::Future;
+        use std::task::Poll;
+
+        loop {
+            match &mut self.backend {
+                #[cfg(target_os = "linux")]
+                AsyncBackend::Inotify(x) => {
+                    let mut ready = Vec::new();
+                    let drained = match x.poll_read_ready_mut(cx) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Ok(mut guard)) => {
+                            let drained = guard.get_inner_mut().drain(&mut ready);
+                            // drain reads until EAGAIN.
+                            guard.clear_ready();
+                            drained
+                        }
+                        Poll::Ready(Err(err)) => Err(err),
+                    };
+                    match drained {
+                        Ok(()) if ready.is_empty() => {}
+                        Ok(()) => return Poll::Ready(()),
+                        Err(_) => self.backend = AsyncBackend::Poll(DEFAULT_POLL_INTERVAL, None),
+                    }
+                }
+                AsyncBackend::Poll(interval, sleep) => {
+                    let timer =
+                        sleep.get_or_insert_with(|| Box::pin(tokio::time::sleep(*interval)));
+                    if timer.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    *sleep = None;
+                    return Poll::Ready(());
+                }
+            }
+        }
+    }
+
+    pub(crate) fn rewatch(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let AsyncBackend::Inotify(x) = &mut self.backend {
+            x.get_mut().rewatch(0);
+        }
+    }
+}
+
+fn pollfd(fd: RawFd) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
+
+/// poll(2) that retries on EINTR. `None` waits forever. Returns the number
+/// of ready descriptors, 0 on timeout.
+fn poll(fds: &mut [libc::pollfd], timeout: Option<Duration>) -> io::Result<usize> {
+    let timeout = match timeout {
+        // Round up so that a sub-millisecond remainder doesn't spin.
+        Some(t) => t.as_micros().div_ceil(1000).min(libc::c_int::MAX as u128) as libc::c_int,
+        None => -1,
+    };
+    loop {
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if ready >= 0 {
+            return Ok(ready as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+fn parent_dir(filename: &Path) -> PathBuf {
+    match filename.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr, OsString};
+    use std::io;
+    use std::mem::size_of;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use super::{parent_dir, poll, pollfd};
+
+    const FILE_MASK: u32 =
+        libc::IN_MODIFY | libc::IN_ATTRIB | libc::IN_MOVE_SELF | libc::IN_DELETE_SELF;
+    const DIR_MASK: u32 =
+        libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+
+    /// One inotify instance shared by every watched file. Files in the same
+    /// directory, or hard links to the same inode, share a watch
+    /// descriptor, so descriptors are reference counted.
+    pub(crate) struct Inotify {
+        fd: OwnedFd,
+        targets: HashMap<usize, Target>,
+        refs: HashMap<i32, usize>,
+    }
+
+    /// A file and the directory holding it, or a bare directory whose
+    /// every entry is of interest.
+    struct Target {
+        dir_wd: i32,
+        file: Option<FileTarget>,
+    }
+
+    struct FileTarget {
+        filename: CString,
+        basename: OsString,
+        wd: Option<i32>,
+    }
+
+    impl Target {
+        fn matches(&self, wd: i32, name: &OsStr) -> bool {
+            match &self.file {
+                Some(f) => Some(wd) == f.wd || (wd == self.dir_wd && name == f.basename),
+                None => wd == self.dir_wd,
+            }
+        }
+    }
+
+    impl Inotify {
+        pub(crate) fn new() -> io::Result<Inotify> {
+            let raw = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if raw < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Inotify {
+                fd: unsafe { OwnedFd::from_raw_fd(raw) },
+                targets: HashMap::new(),
+                refs: HashMap::new(),
+            })
+        }
+
+        pub(crate) fn add(&mut self, id: usize, filename: &Path) -> io::Result<()> {
+            let filename_c = cstring(filename)?;
+            let dir_wd = self.add_watch(&cstring(&parent_dir(filename))?, DIR_MASK)?;
+            self.targets.insert(
+                id,
+                Target {
+                    dir_wd,
+                    file: Some(FileTarget {
+                        filename: filename_c,
+                        basename: filename.file_name().unwrap_or_default().to_os_string(),
+                        wd: None,
+                    }),
+                },
+            );
+            self.rewatch(id);
+            Ok(())
+        }
+
+        pub(crate) fn add_dir(&mut self, id: usize, dir: &Path) -> io::Result<()> {
+            let dir_wd = self.add_watch(&cstring(dir)?, DIR_MASK)?;
+            self.targets.insert(id, Target { dir_wd, file: None });
+            Ok(())
+        }
+
+        pub(crate) fn remove(&mut self, id: usize) {
+            if let Some(target) = self.targets.remove(&id) {
+                if let Some(wd) = target.file.and_then(|f| f.wd) {
+                    self.rm_watch(wd);
+                }
+                self.rm_watch(target.dir_wd);
+            }
+        }
+
+        pub(crate) fn rewatch(&mut self, id: usize) {
+            let (old, filename) = match self.targets.get_mut(&id).and_then(|t| t.file.as_mut()) {
+                Some(f) => (f.wd.take(), f.filename.clone()),
+                None => return,
+            };
+            if let Some(wd) = old {
+                self.rm_watch(wd);
+            }
+            // The file may be missing right now; the directory watch
+            // reports when it shows up again.
+            let wd = self.add_watch(&filename, FILE_MASK).ok();
+            if let Some(f) = self.targets.get_mut(&id).and_then(|t| t.file.as_mut()) {
+                f.wd = wd;
+            }
+        }
+
+        /// Blocks until an event for a watched file arrives, `wake`
+        /// becomes readable or `timeout` runs out.
+        pub(crate) fn wait(
+            &mut self,
+            wake: RawFd,
+            timeout: Option<Duration>,
+            ready: &mut Vec<usize>,
+        ) -> io::Result<()> {
+            let deadline = timeout.and_then(|t| Instant::now().checked_add(t));
+            let before = ready.len();
+            loop {
+                let timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                let mut fds = [pollfd(self.fd.as_raw_fd()), pollfd(wake)];
+                if poll(&mut fds, timeout)? == 0 || fds[1].revents != 0 {
+                    return Ok(());
+                }
+                self.drain(ready)?;
+                if ready.len() > before {
+                    return Ok(());
+                }
+            }
+        }
+
+        /// Reads every queued event and appends the ids of the files they
+        /// concern to `ready`.
+        pub(crate) fn drain(&mut self, ready: &mut Vec<usize>) -> io::Result<()> {
+            let mut buf = [0u8; 4096];
+            loop {
+                let len =
+                    unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if len < 0 {
+                    let err = io::Error::last_os_error();
+                    return match err.kind() {
+                        io::ErrorKind::WouldBlock => Ok(()),
+                        io::ErrorKind::Interrupted => continue,
+                        _ => Err(err),
+                    };
+                }
+                let mut offset = 0;
+                while offset + size_of::<libc::inotify_event>() <= len as usize {
+                    let event: libc::inotify_event =
+                        unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset).cast()) };
+                    let name_start = offset + size_of::<libc::inotify_event>();
+                    let name = &buf[name_start..name_start + event.len as usize];
+                    let name = OsStr::from_bytes(name.split(|&b| b == 0).next().unwrap_or(&[]));
+                    // On overflow events were lost, so anything may have
+                    // changed.
+                    let overflow = event.mask & libc::IN_Q_OVERFLOW != 0;
+                    for (&id, target) in &self.targets {
+                        if (overflow || target.matches(event.wd, name)) && !ready.contains(&id) {
+                            ready.push(id);
+                        }
+                    }
+                    offset = name_start + event.len as usize;
+                }
+            }
+        }
+
+        fn add_watch(&mut self, path: &CString, mask: u32) -> io::Result<i32> {
+            let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            *self.refs.entry(wd).or_insert(0) += 1;
+            Ok(wd)
+        }
+
+        fn rm_watch(&mut self, wd: i32) {
+            if let Some(count) = self.refs.get_mut(&wd) {
+                *count -= 1;
+                if *count == 0 {
+                    self.refs.remove(&wd);
+                    unsafe { libc::inotify_rm_watch(self.fd.as_raw_fd(), wd) };
+                }
+            }
+        }
+    }
+
+    impl AsRawFd for Inotify {
+        fn as_raw_fd(&self) -> RawFd {
+            self.fd.as_raw_fd()
+        }
+    }
+
+    fn cstring(path: &Path) -> io::Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
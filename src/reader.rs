@@ -0,0 +1,248 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::Delimiter;
+
+/// Reads a file in large chunks and splits lines in place in its buffer,
+/// which is reused from one read to the next. Nothing is copied out until
+/// a line is handed out, and the file is only read or seeked when the
+/// buffer runs dry or on request, never once per line.
+///
+/// The file position is always just past the buffered bytes.
+pub(crate) struct LineReader {
+    file: File,
+    buf: Vec<u8>,
+    /// The bytes not consumed yet are `buf[start..end]`.
+    start: usize,
+    end: usize,
+    /// What the buffer shrinks back to after a long line.
+    capacity: usize,
+}
+
+impl LineReader {
+    pub(crate) fn new(file: File, capacity: usize) -> LineReader {
+        LineReader {
+            file,
+            buf: Vec::new(),
+            start: 0,
+            end: 0,
+            capacity,
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// What has been read but not consumed yet.
+    pub(crate) fn buffered(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    /// Finds the next line at the start of `buffered()`, reading more as
+    /// needed, and returns how long it is with its delimiter. A line
+    /// reaching `limit` bytes without a delimiter ends there. Returns
+    /// `None` at EOF, with whatever is left unterminated in `buffered()`.
+    pub(crate) fn next_line(
+        &mut self,
+        delimiter: &Delimiter,
+        limit: Option<usize>,
+    ) -> io::Result<Option<usize>> {
+        loop {
+            let buffered = self.buffered();
+            let data = match limit {
+                Some(limit) => &buffered[..buffered.len().min(limit)],
+                None => buffered,
+            };
+            if let Some(len) = delimiter.find(data) {
+                return Ok(Some(len));
+            }
+            if limit.is_some_and(|l| data.len() >= l) {
+                return Ok(Some(data.len()));
+            }
+            if self.fill()? == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Drops the first `n` buffered bytes.
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.start = (self.start + n).min(self.end);
+        if self.start == self.end {
+            self.start = 0;
+            self.end = 0;
+        }
+    }
+
+    /// Drops the first `n` buffered bytes and returns them. They are only
+    /// overwritten by the next read.
+    pub(crate) fn take(&mut self, n: usize) -> &[u8] {
+        let start = self.start;
+        let n = n.min(self.end - start);
+        self.consume(n);
+        &self.buf[start..start + n]
+    }
+
+    /// Moves to `pos`, dropping everything buffered.
+    pub(crate) fn seek(&mut self, pos: u64) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.consume(self.end);
+        Ok(())
+    }
+
+    /// Changes how much is read at a time. Buffered bytes are kept.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.compact();
+        self.buf.resize(capacity.max(self.end), 0);
+        self.buf.shrink_to_fit();
+    }
+
+    /// Reads more into the buffer after what is there, making room first
+    /// if it is full. Returns how many bytes were read, 0 at EOF.
+    fn fill(&mut self) -> io::Result<usize> {
+        if self.end == 0 && self.buf.len() > self.capacity {
+            // Done with a long line.
+            self.buf.truncate(self.capacity);
+            self.buf.shrink_to_fit();
+        }
+        if self.end == self.buf.len() {
+            if self.start > 0 {
+                self.compact();
+            } else {
+                // A line longer than the buffer.
+                let len = (self.buf.len() * 2).max(self.capacity);
+                self.buf.resize(len, 0);
+            }
+        }
+        loop {
+            match self.file.read(&mut self.buf[self.end..]) {
+                Ok(n) => {
+                    self.end += n;
+                    return Ok(n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Moves the buffered bytes to the front of the buffer.
+    fn compact(&mut self) {
+        self.buf.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn reader(file: &TempFile, capacity: usize) -> LineReader {
+        LineReader::new(File::open(file.path()).unwrap(), capacity)
+    }
+
+    /// Reads every complete line, each with its delimiter.
+    fn lines(reader: &mut LineReader, delimiter: &Delimiter) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Some(len) = reader.next_line(delimiter, None).unwrap() {
+            lines.push(String::from_utf8(reader.take(len).to_vec()).unwrap());
+        }
+        lines
+    }
+
+    #[test]
+    fn delimiter_across_a_refill() {
+        let file = TempFile::new(b"ab\r\ncd\r\n");
+        let mut reader = reader(&file, 3);
+        assert_eq!(lines(&mut reader, &Delimiter::CrLf), ["ab\r\n", "cd\r\n"]);
+        assert_eq!(reader.buffered(), b"");
+    }
+
+    #[test]
+    fn sequence_split_across_reads() {
+        let file = TempFile::new(b"abc<EOR>de<EOR>f");
+        let mut reader = reader(&file, 4);
+        let delimiter = Delimiter::Sequence(b"<EOR>".to_vec());
+        assert_eq!(lines(&mut reader, &delimiter), ["abc<EOR>", "de<EOR>"]);
+        assert_eq!(reader.buffered(), b"f");
+    }
+
+    #[test]
+    fn line_ends_at_the_limit() {
+        let file = TempFile::new(b"abcdefghij\n");
+        let mut reader = reader(&file, 4);
+        let limit = Some(6);
+        assert_eq!(
+            reader.next_line(&Delimiter::Newline, limit).unwrap(),
+            Some(6)
+        );
+        assert_eq!(reader.take(6), b"abcdef");
+        assert_eq!(
+            reader.next_line(&Delimiter::Newline, limit).unwrap(),
+            Some(5)
+        );
+        assert_eq!(reader.take(5), b"ghij\n");
+    }
+
+    #[test]
+    fn unterminated_line_is_completed_by_a_later_write() {
+        let file = TempFile::new(b"ab");
+        let mut reader = reader(&file, 8);
+        assert_eq!(reader.next_line(&Delimiter::Newline, None).unwrap(), None);
+        assert_eq!(reader.buffered(), b"ab");
+        let mut f = OpenOptions::new().append(true).open(file.path()).unwrap();
+        f.write_all(b"c\n").unwrap();
+        assert_eq!(lines(&mut reader, &Delimiter::Newline), ["abc\n"]);
+    }
+
+    #[test]
+    fn buffer_grows_for_a_long_line_and_shrinks_back() {
+        let mut contents = b"a".repeat(100);
+        contents.push(b'\n');
+        let file = TempFile::new(&contents);
+        let mut reader = reader(&file, 4);
+        assert_eq!(
+            reader.next_line(&Delimiter::Newline, None).unwrap(),
+            Some(101)
+        );
+        assert!(reader.buf.len() >= 101);
+        reader.consume(101);
+        assert_eq!(reader.next_line(&Delimiter::Newline, None).unwrap(), None);
+        assert_eq!(reader.buf.len(), 4);
+    }
+
+    #[test]
+    fn seek_drops_what_is_buffered() {
+        let file = TempFile::new(b"abc\ndef\n");
+        let mut reader = reader(&file, 16);
+        assert_eq!(
+            reader.next_line(&Delimiter::Newline, None).unwrap(),
+            Some(4)
+        );
+        reader.seek(4).unwrap();
+        assert_eq!(reader.buffered(), b"");
+        assert_eq!(lines(&mut reader, &Delimiter::Newline), ["def\n"]);
+    }
+
+    #[test]
+    fn set_capacity_keeps_what_is_buffered() {
+        let file = TempFile::new(b"abc\ndef\nghi\n");
+        let mut reader = reader(&file, 16);
+        assert_eq!(
+            reader.next_line(&Delimiter::Newline, None).unwrap(),
+            Some(4)
+        );
+        reader.consume(4);
+        reader.set_capacity(2);
+        assert_eq!(reader.buffered(), b"def\nghi\n");
+        assert_eq!(lines(&mut reader, &Delimiter::Newline), ["def\n", "ghi\n"]);
+    }
+}
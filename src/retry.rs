@@ -0,0 +1,175 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+
+/// How a watcher retries after an IO failure, such as a rotated file it is
+/// not allowed to open or a read that fails.
+///
+/// Each failure is delivered as an error event. The wait before the next
+/// attempt starts at `initial` and doubles with every failure in a row, up
+/// to `max`. By default the watcher never gives up; with `max_attempts` or
+/// `max_elapsed` set, it stops following the file once either is reached
+/// and delivers `LogWatcherError::GaveUp`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub initial: Duration,
+    pub max: Duration,
+    /// Share of each wait, between 0.0 and 1.0, that is randomly added or
+    /// taken off so that many watchers don't retry in lockstep.
+    pub jitter: f64,
+    /// Give up after this many failures in a row.
+    pub max_attempts: Option<u32>,
+    /// Give up once failures have gone on for this long.
+    pub max_elapsed: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            jitter: 0.2,
+            max_attempts: None,
+            max_elapsed: None,
+        }
+    }
+}
+
+/// Failures in a row under a `RetryPolicy`.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    policy: RetryPolicy,
+    attempts: u32,
+    first: Option<Instant>,
+    /// When to try again, while waiting out a failure. A wait too long to
+    /// have an end never does.
+    next: Option<Option<Instant>>,
+}
+
+impl Backoff {
+    pub(crate) fn new(policy: RetryPolicy) -> Backoff {
+        Backoff {
+            policy,
+            attempts: 0,
+            first: None,
+            next: None,
+        }
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    pub(crate) fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Records a failure and schedules the next attempt. Returns `false`
+    /// once the policy says to give up.
+    pub(crate) fn failed(&mut self) -> bool {
+        let now = Instant::now();
+        self.attempts += 1;
+        let first = *self.first.get_or_insert(now);
+        if self.policy.max_attempts.is_some_and(|m| self.attempts >= m)
+            || self.policy.max_elapsed.is_some_and(|m| now - first >= m)
+        {
+            self.next = None;
+            return false;
+        }
+        let doublings = (self.attempts - 1).min(31);
+        let delay = self
+            .policy
+            .initial
+            .saturating_mul(1 << doublings)
+            .min(self.policy.max);
+        let jitter = self.policy.jitter.clamp(0.0, 1.0);
+        // Jitter can double the wait, which mustn't overflow.
+        let delay = delay
+            .min(Duration::MAX / 4)
+            .mul_f64(1.0 + jitter * (2.0 * random() - 1.0));
+        self.next = Some(now.checked_add(delay));
+        true
+    }
+
+    pub(crate) fn succeeded(&mut self) {
+        self.attempts = 0;
+        self.first = None;
+        self.next = None;
+    }
+
+    /// When the next attempt is due, while waiting out a failure.
+    pub(crate) fn retry_at(&self) -> Option<Instant> {
+        self.next.flatten()
+    }
+
+    /// Whether it is time to try again, or there was no failure.
+    pub(crate) fn ready(&self) -> bool {
+        match self.next {
+            Some(Some(at)) => Instant::now() >= at,
+            Some(None) => false,
+            None => true,
+        }
+    }
+}
+
+/// A number in `[0, 1)`, good enough for jitter. `RandomState` is seeded
+/// randomly and differently every time.
+fn random() -> f64 {
+    let x = RandomState::new().build_hasher().finish();
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(initial: Duration, max: Duration) -> RetryPolicy {
+        RetryPolicy {
+            initial,
+            max,
+            jitter: 0.0,
+            max_attempts: None,
+            max_elapsed: None,
+        }
+    }
+
+    #[test]
+    fn waits_double_up_to_max() {
+        let mut backoff = Backoff::new(policy(Duration::from_secs(10), Duration::from_secs(30)));
+        let mut waits = Vec::new();
+        for _ in 0..4 {
+            let before = Instant::now();
+            assert!(backoff.failed());
+            let wait = backoff.retry_at().unwrap() - before;
+            waits.push(wait.as_secs());
+            assert!(!backoff.ready());
+        }
+        assert_eq!(waits, [10, 20, 30, 30]);
+        backoff.succeeded();
+        assert!(backoff.ready());
+        assert_eq!(backoff.attempts(), 0);
+    }
+
+    #[test]
+    fn longest_wait_does_not_overflow() {
+        let mut backoff = Backoff::new(RetryPolicy {
+            jitter: 1.0,
+            ..policy(Duration::MAX, Duration::MAX)
+        });
+        for _ in 0..10 {
+            assert!(backoff.failed());
+            assert!(!backoff.ready());
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut backoff = Backoff::new(RetryPolicy {
+            max_attempts: Some(2),
+            ..policy(Duration::from_secs(1), Duration::from_secs(1))
+        });
+        assert!(backoff.failed());
+        assert!(!backoff.failed());
+        assert_eq!(backoff.retry_at(), None);
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Identifies a file independently of its name: the device it lives on
+/// and its inode number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl FileId {
+    pub(crate) fn of(metadata: &Metadata) -> FileId {
+        FileId {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+}
+
+/// How the watched file was rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationKind {
+    /// The old file was moved away and a new one created in its place.
+    Renamed,
+    /// The old file was deleted and a new one created in its place.
+    Recreated,
+    /// The file was truncated in place, as with logrotate's
+    /// `copytruncate`. Reading starts over at the beginning; lines written
+    /// after the last read but before the truncation are lost.
+    Truncated,
+    /// The file was deleted and nothing has replaced it yet. Another
+    /// rotation follows once a new file shows up.
+    Deleted,
+    /// The path is now a symlink to a different file.
+    ReplacedBySymlink,
+}
+
+/// Which kinds of rotation a watcher looks out for. Both are on by
+/// default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationDetection {
+    /// Follow the path: once it points at a different file, read the old
+    /// one to its end and switch over. Without this the file opened first
+    /// is followed for good, like `tail -f` rather than `tail -F`, and
+    /// neither renames nor deletions are reported.
+    pub reopen: bool,
+    /// Start over at the beginning when the file shrinks below what has
+    /// been read from it.
+    pub truncation: bool,
+}
+
+impl Default for RotationDetection {
+    fn default() -> RotationDetection {
+        RotationDetection {
+            reopen: true,
+            truncation: true,
+        }
+    }
+}
+
+/// Details of a rotation, delivered with `LogWatcherEvent::Rotation` once
+/// everything left in the old file has been read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    pub kind: RotationKind,
+    /// The file read until now.
+    pub old: FileId,
+    /// The file read from now on, or `None` after a deletion.
+    pub new: Option<FileId>,
+    /// How far the old file was read.
+    pub offset: u64,
+}
+
+/// Works out how the file at `filename` came to replace `old`.
+pub(crate) fn kind(filename: &Path, old: &Metadata) -> RotationKind {
+    let symlink = fs::symlink_metadata(filename)
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false);
+    if symlink {
+        RotationKind::ReplacedBySymlink
+    } else if old.nlink() == 0 {
+        RotationKind::Recreated
+    } else {
+        RotationKind::Renamed
+    }
+}
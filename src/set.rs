@@ -0,0 +1,779 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::checkpoint::CheckpointFile;
+use crate::follower::Follower;
+use crate::notify::Notifier;
+use crate::{
+    Delimiter, Discovery, EncodingPolicy, LineLimit, LogWatcherAction, LogWatcherError,
+    LogWatcherEvent, Multiline, RetryPolicy, StartPosition, StopHandle, StopReason, WatchMode,
+};
+
+/// Follows any number of log files from a single thread.
+///
+/// All files share one notifier, so an idle set costs one blocked thread
+/// no matter how many files it holds. Every event comes with the path of
+/// the file it was read from. Files can be added and removed at any time,
+/// including from other threads while `watch` runs, through a
+/// `LogWatcherSetHandle`.
+///
+/// Besides single files, a set can follow whatever appears in a directory
+/// through `discover`.
+pub struct LogWatcherSet {
+    files: HashMap<usize, Entry>,
+    ids: HashMap<PathBuf, usize>,
+    discoveries: HashMap<usize, Discovered>,
+    next_id: usize,
+    /// Files and directories that may have changed, in round-robin order.
+    dirty: VecDeque<usize>,
+    notifier: Notifier,
+    stop_handle: StopHandle,
+    stopped: Option<StopReason>,
+    /// Whether positions have been saved since the set stopped, so that a
+    /// failure to is reported only once.
+    saved_on_stop: bool,
+    commands: Receiver<Command>,
+    sender: Sender<Command>,
+    pending: VecDeque<Tagged>,
+    checkpoints: Option<CheckpointFile>,
+    retry_policy: RetryPolicy,
+    missing_timeout: Option<Duration>,
+    encoding: EncodingPolicy,
+    flush_timeout: Option<Duration>,
+    delimiter: Delimiter,
+    line_limit: Option<LineLimit>,
+    multiline: Option<Multiline>,
+}
+
+/// An event with the id and path of the file it came from. Errors about
+/// files that could not be added have no id.
+type Tagged = (
+    Option<usize>,
+    Arc<Path>,
+    Result<LogWatcherEvent, LogWatcherError>,
+);
+
+struct Entry {
+    path: Arc<Path>,
+    follower: Follower,
+}
+
+struct Discovered {
+    rules: Discovery,
+    /// Files this discovery is following.
+    members: HashSet<PathBuf>,
+    /// Matching files not to pick up again: removed by hand, or failed to
+    /// open. Forgotten once they leave the directory.
+    ignored: HashSet<PathBuf>,
+}
+
+enum Command {
+    Add(PathBuf),
+    Remove(PathBuf),
+    Discover(Discovery),
+}
+
+/// Adds files to, removes files from, or stops a `LogWatcherSet` from any
+/// thread.
+///
+/// Changes take effect the next time the set looks for events. If adding
+/// a file fails, the error is delivered as an event for that path.
+#[derive(Clone)]
+pub struct LogWatcherSetHandle {
+    stop_handle: StopHandle,
+    commands: Sender<Command>,
+}
+
+impl LogWatcherSetHandle {
+    pub fn add<P: AsRef<Path>>(&self, filename: P) {
+        self.send(Command::Add(filename.as_ref().to_path_buf()));
+    }
+
+    pub fn remove<P: AsRef<Path>>(&self, filename: P) {
+        self.send(Command::Remove(filename.as_ref().to_path_buf()));
+    }
+
+    pub fn discover(&self, discovery: Discovery) {
+        self.send(Command::Discover(discovery));
+    }
+
+    pub fn stop(&self) {
+        self.stop_handle.stop();
+    }
+
+    fn send(&self, command: Command) {
+        // The set only goes away together with the receiver, and then
+        // there is nothing left to change.
+        if self.commands.send(command).is_ok() {
+            self.stop_handle.wake();
+        }
+    }
+}
+
+impl LogWatcherSet {
+    pub fn new() -> Result<LogWatcherSet, LogWatcherError> {
+        LogWatcherSet::with_mode(WatchMode::default())
+    }
+
+    pub fn with_mode(mode: WatchMode) -> Result<LogWatcherSet, LogWatcherError> {
+        LogWatcherSet::open(mode, None)
+    }
+
+    /// A set that keeps how far each file has been read in `state_file`,
+    /// like `LogWatcher::register_with_checkpoint`. Files added later, by
+    /// hand or through discovery, continue from their saved position if
+    /// there is one. Removing a file, or losing a discovered one, forgets
+    /// its position. Errors saving the state file are delivered as events
+    /// for its path.
+    pub fn with_checkpoint<P: AsRef<Path>>(
+        state_file: P,
+    ) -> Result<LogWatcherSet, LogWatcherError> {
+        let state_file = state_file.as_ref();
+        let checkpoints = CheckpointFile::load(state_file)
+            .map_err(|e| LogWatcherError::checkpoint(state_file, e))?;
+        LogWatcherSet::open(WatchMode::default(), Some(checkpoints))
+    }
+
+    fn open(
+        mode: WatchMode,
+        checkpoints: Option<CheckpointFile>,
+    ) -> Result<LogWatcherSet, LogWatcherError> {
+        let (stop_handle, wake) = StopHandle::new()?;
+        let (sender, commands) = channel();
+        Ok(LogWatcherSet {
+            files: HashMap::new(),
+            ids: HashMap::new(),
+            discoveries: HashMap::new(),
+            next_id: 0,
+            dirty: VecDeque::new(),
+            notifier: Notifier::new(mode, wake),
+            stop_handle,
+            stopped: None,
+            saved_on_stop: false,
+            commands,
+            sender,
+            pending: VecDeque::new(),
+            checkpoints,
+            retry_policy: RetryPolicy::default(),
+            missing_timeout: None,
+            encoding: EncodingPolicy::default(),
+            flush_timeout: None,
+            delimiter: Delimiter::default(),
+            line_limit: None,
+            multiline: None,
+        })
+    }
+
+    /// Starts following `filename` from its current end. Adding a path
+    /// that is already in the set does nothing.
+    pub fn add<P: AsRef<Path>>(&mut self, filename: P) -> Result<(), LogWatcherError> {
+        let filename = filename.as_ref();
+        self.add_at(filename, StartPosition::End)
+    }
+
+    /// Like `add`, but starts reading at `start`.
+    pub fn add_at<P: AsRef<Path>>(
+        &mut self,
+        filename: P,
+        start: StartPosition,
+    ) -> Result<(), LogWatcherError> {
+        let filename = filename.as_ref();
+        if self.ids.contains_key(filename) {
+            return Ok(());
+        }
+        self.insert(filename, start, false)?;
+        Ok(())
+    }
+
+    /// Like `add`, but `filename` may not exist yet. A missing file is
+    /// read from the start once it appears, like
+    /// `LogWatcher::register_allow_missing`.
+    pub fn add_allow_missing<P: AsRef<Path>>(
+        &mut self,
+        filename: P,
+    ) -> Result<(), LogWatcherError> {
+        let filename = filename.as_ref();
+        if self.ids.contains_key(filename) {
+            return Ok(());
+        }
+        self.insert(filename, StartPosition::End, true)?;
+        Ok(())
+    }
+
+    fn insert(
+        &mut self,
+        filename: &Path,
+        start: StartPosition,
+        allow_missing: bool,
+    ) -> Result<usize, LogWatcherError> {
+        let id = self.next_id;
+        self.notifier.add(id, filename);
+        let opened = match self.checkpoints.as_ref().and_then(|c| c.get(filename)) {
+            Some(checkpoint) if allow_missing => Follower::resume_or_wait(filename, checkpoint),
+            Some(checkpoint) => Follower::resume(filename, checkpoint),
+            None if allow_missing => Follower::open_or_wait(filename, start, &self.delimiter),
+            None => Follower::open(filename, start, &self.delimiter),
+        };
+        let mut follower = match opened {
+            Ok(x) => x,
+            Err(err) => {
+                self.notifier.remove(id);
+                return Err(err);
+            }
+        };
+        follower.set_retry_policy(self.retry_policy);
+        follower.set_missing_timeout(self.missing_timeout);
+        follower.set_encoding(self.encoding);
+        follower.set_flush_timeout(self.flush_timeout);
+        follower.set_delimiter(self.delimiter.clone());
+        follower.set_line_limit(self.line_limit);
+        follower.set_multiline(self.multiline.clone());
+        // Read whatever is already there to read.
+        self.dirty.push_back(id);
+        self.next_id += 1;
+        self.ids.insert(filename.to_path_buf(), id);
+        self.files.insert(
+            id,
+            Entry {
+                path: Arc::from(filename),
+                follower,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Stops following `filename`. Returns whether it was in the set. A
+    /// discovered file stays ignored until it leaves its directory.
+    pub fn remove<P: AsRef<Path>>(&mut self, filename: P) -> bool {
+        let filename = filename.as_ref();
+        for d in self.discoveries.values_mut() {
+            if d.members.remove(filename) {
+                d.ignored.insert(filename.to_path_buf());
+            }
+        }
+        if let Some(checkpoints) = &mut self.checkpoints {
+            checkpoints.remove(filename);
+        }
+        match self.ids.remove(filename) {
+            Some(id) => {
+                self.files.remove(&id);
+                self.notifier.remove(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Follows every file in the discovery's directory that matches its
+    /// rules, now and whenever one appears later. Files already there are
+    /// followed from their end like `add`; files that show up afterwards
+    /// are read from the start. Each one is announced with
+    /// `LogWatcherEvent::FileDiscovered`. When a discovered file leaves the
+    /// directory, its remaining lines are delivered, followed by
+    /// `LogWatcherEvent::FileLost`, and the set stops following it.
+    pub fn discover(&mut self, discovery: Discovery) -> Result<(), LogWatcherError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.notifier.add_dir(id, discovery.path());
+        let found = match discovery.scan() {
+            Ok(x) => x,
+            Err(err) => {
+                self.notifier.remove(id);
+                return Err(LogWatcherError::open(discovery.path(), 0, err));
+            }
+        };
+        self.discoveries.insert(
+            id,
+            Discovered {
+                rules: discovery,
+                members: HashSet::new(),
+                ignored: HashSet::new(),
+            },
+        );
+        self.pick_up(id, found, StartPosition::End);
+        Ok(())
+    }
+
+    fn rescan(&mut self, id: usize) {
+        let d = match self.discoveries.get_mut(&id) {
+            Some(x) => x,
+            None => return,
+        };
+        let found = match d.rules.scan() {
+            Ok(x) => x,
+            Err(err) => {
+                let err = LogWatcherError::open(d.rules.path(), 0, err);
+                let dir = Arc::from(d.rules.path());
+                self.pending.push_back((None, dir, Err(err)));
+                return;
+            }
+        };
+        d.ignored.retain(|p| found.contains(p));
+        let lost: Vec<PathBuf> = d.members.difference(&found).cloned().collect();
+        d.members.retain(|p| found.contains(p));
+        for path in lost {
+            self.lose(&path);
+        }
+        self.pick_up(id, found, StartPosition::Beginning);
+    }
+
+    /// Delivers what is left of a discovered file that went away, then
+    /// drops it.
+    fn lose(&mut self, filename: &Path) {
+        let id = match self.ids.remove(filename) {
+            Some(x) => x,
+            None => return,
+        };
+        if let Some(checkpoints) = &mut self.checkpoints {
+            checkpoints.remove(filename);
+        }
+        if let Some(mut entry) = self.files.remove(&id) {
+            entry.follower.close();
+            while let Some(event) = entry.follower.next_event() {
+                self.pending
+                    .push_back((Some(id), entry.path.clone(), event));
+            }
+            let event = Ok(LogWatcherEvent::FileLost);
+            self.pending.push_back((Some(id), entry.path, event));
+        }
+        self.notifier.remove(id);
+    }
+
+    fn pick_up(&mut self, id: usize, found: HashSet<PathBuf>, start: StartPosition) {
+        let mut new: Vec<PathBuf> = match self.discoveries.get(&id) {
+            Some(d) => found
+                .into_iter()
+                .filter(|p| !d.members.contains(p) && !d.ignored.contains(p))
+                .filter(|p| !self.ids.contains_key(p))
+                .collect(),
+            None => return,
+        };
+        new.sort();
+        for path in new {
+            let result = self.insert(&path, start, false);
+            let d = match self.discoveries.get_mut(&id) {
+                Some(x) => x,
+                None => return,
+            };
+            match result {
+                Ok(file) => {
+                    d.members.insert(path.clone());
+                    let event = Ok(LogWatcherEvent::FileDiscovered);
+                    self.pending.push_back((Some(file), Arc::from(path), event));
+                }
+                Err(err) => {
+                    // Reported once; retried if the file comes back.
+                    d.ignored.insert(path.clone());
+                    self.pending.push_back((None, Arc::from(path), Err(err)));
+                }
+            }
+        }
+    }
+
+    /// Drops a file that failed for good. Unlike `remove`, its checkpoint
+    /// is kept.
+    fn give_up(&mut self, id: usize) {
+        if let Some(entry) = self.files.remove(&id) {
+            self.ids.remove(&*entry.path);
+            for d in self.discoveries.values_mut() {
+                if d.members.remove(&*entry.path) {
+                    d.ignored.insert(entry.path.to_path_buf());
+                }
+            }
+        }
+        self.notifier.remove(id);
+    }
+
+    /// Changes how failures reading a file, or opening it after a rotation,
+    /// are retried, for files in the set and files added later. A file
+    /// whose policy runs out is dropped from the set after
+    /// `LogWatcherError::GaveUp`; the other files carry on.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+        for entry in self.files.values_mut() {
+            entry.follower.set_retry_policy(policy);
+        }
+    }
+
+    /// Drops files from the set once they have been missing for `timeout`,
+    /// after `LogWatcherError::FileVanished`, for files in the set and
+    /// files added later.
+    pub fn set_missing_timeout(&mut self, timeout: Option<Duration>) {
+        self.missing_timeout = timeout;
+        for entry in self.files.values_mut() {
+            entry.follower.set_missing_timeout(timeout);
+        }
+    }
+
+    /// Changes what happens to lines that are not valid UTF-8, for files in
+    /// the set and files added later.
+    pub fn set_encoding(&mut self, encoding: EncodingPolicy) {
+        self.encoding = encoding;
+        for entry in self.files.values_mut() {
+            entry.follower.set_encoding(encoding);
+        }
+    }
+
+    /// Delivers lines that have been waiting for their delimiter for
+    /// `timeout`, like `LogWatcher::set_flush_timeout`, for files in the
+    /// set and files added later.
+    pub fn set_flush_timeout(&mut self, timeout: Option<Duration>) {
+        self.flush_timeout = timeout;
+        for entry in self.files.values_mut() {
+            entry.follower.set_flush_timeout(timeout);
+        }
+    }
+
+    /// Changes what ends a line, for files in the set and files added
+    /// later.
+    pub fn set_delimiter(&mut self, delimiter: Delimiter) {
+        for entry in self.files.values_mut() {
+            entry.follower.set_delimiter(delimiter.clone());
+        }
+        self.delimiter = delimiter;
+    }
+
+    /// Caps how long a line can get, for files in the set and files added
+    /// later.
+    pub fn set_line_limit(&mut self, limit: Option<LineLimit>) {
+        self.line_limit = limit;
+        for entry in self.files.values_mut() {
+            entry.follower.set_line_limit(limit);
+        }
+    }
+
+    /// Joins lines into records, like `LogWatcher::set_multiline`, for
+    /// files in the set and files added later.
+    pub fn set_multiline(&mut self, multiline: Option<Multiline>) {
+        for entry in self.files.values_mut() {
+            entry.follower.set_multiline(multiline.clone());
+        }
+        self.multiline = multiline;
+    }
+
+    pub fn contains<P: AsRef<Path>>(&self, filename: P) -> bool {
+        self.ids.contains_key(filename.as_ref())
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.values().map(|e| &*e.path)
+    }
+
+    pub fn handle(&self) -> LogWatcherSetHandle {
+        LogWatcherSetHandle {
+            stop_handle: self.stop_handle.clone(),
+            commands: self.sender.clone(),
+        }
+    }
+
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop_handle.clone()
+    }
+
+    /// Why the set stopped, or `None` while it is still running.
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        if self.stopped.is_some() {
+            return self.stopped;
+        }
+        if self.stop_handle.is_stopped() {
+            return Some(StopReason::Handle);
+        }
+        None
+    }
+
+    /// Applies an action to the file at `filename`, the way `watch` does
+    /// with the callback's return value. `LogWatcherAction::Stop` stops the
+    /// whole set.
+    pub fn handle_action<P: AsRef<Path>>(&mut self, filename: P, action: LogWatcherAction) {
+        let id = self.ids.get(filename.as_ref()).copied();
+        self.apply(id, action);
+    }
+
+    fn apply(&mut self, id: Option<usize>, action: LogWatcherAction) {
+        if let LogWatcherAction::Stop = action {
+            self.stopped = Some(StopReason::Callback);
+        }
+        let entry = match id.and_then(|id| self.files.get_mut(&id)) {
+            Some(entry) => entry,
+            None => return,
+        };
+        if let LogWatcherAction::SkipFile = action {
+            let path = entry.path.clone();
+            self.remove(&*path);
+            return;
+        }
+        entry.follower.handle_action(&action);
+    }
+
+    fn run_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                Command::Add(filename) => {
+                    if let Err(err) = self.add(&filename) {
+                        self.pending
+                            .push_back((None, Arc::from(filename), Err(err)));
+                    }
+                }
+                Command::Remove(filename) => {
+                    self.remove(&filename);
+                }
+                Command::Discover(discovery) => {
+                    let dir = Arc::from(discovery.path());
+                    if let Err(err) = self.discover(discovery) {
+                        self.pending.push_back((None, dir, Err(err)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits at most `timeout` for the next event. Returns `None` if nothing
+    /// happened in time or the set has been stopped; `stop_reason` tells
+    /// the two apart.
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Option<(Arc<Path>, Result<LogWatcherEvent, LogWatcherError>)> {
+        // A timeout too long to represent is no timeout at all.
+        self.next_event(Instant::now().checked_add(timeout))
+            .map(|(_, path, event)| (path, event))
+    }
+
+    fn next_event(&mut self, deadline: Option<Instant>) -> Option<Tagged> {
+        let mut ready = Vec::new();
+        loop {
+            if self.stop_reason().is_some() {
+                if std::mem::replace(&mut self.saved_on_stop, true) {
+                    return None;
+                }
+                return self
+                    .save_checkpoints(true)
+                    .err()
+                    .map(|err| self.state_error(err));
+            }
+            self.saved_on_stop = false;
+            self.run_commands();
+            if let Err(err) = self.save_checkpoints(false) {
+                return Some(self.state_error(err));
+            }
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            if let Some(id) = self.dirty.pop_front() {
+                if self.discoveries.contains_key(&id) {
+                    self.rescan(id);
+                } else if let Some(entry) = self.files.get_mut(&id) {
+                    let event = entry.follower.next_event();
+                    if entry.follower.take_reopened() {
+                        self.notifier.rewatch(id);
+                        // Writes to the new file that landed before the
+                        // watch moved over raised no event, so read once
+                        // more.
+                        if event.is_none() {
+                            self.dirty.push_back(id);
+                        }
+                    }
+                    if let Some(event) = event {
+                        let path = entry.path.clone();
+                        if entry.follower.finished() {
+                            self.give_up(id);
+                        } else {
+                            // Come back to this file after the others had a
+                            // turn.
+                            self.dirty.push_back(id);
+                        }
+                        return Some((Some(id), path, event));
+                    }
+                }
+                continue;
+            }
+            let mut timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(t) if !t.is_zero() => Some(t),
+                    _ => return None,
+                },
+                None => None,
+            };
+            // Wake up in time to save the positions we caught up at.
+            self.record_checkpoints();
+            if let Some(due) = self.checkpoints.as_ref().and_then(|c| c.due_in()) {
+                timeout = Some(timeout.map_or(due, |t| t.min(due)));
+            }
+            // Retry after failures or report missing files even if nothing
+            // changes.
+            let wake_at = self
+                .files
+                .values()
+                .filter_map(|e| e.follower.wake_at())
+                .min();
+            if let Some(at) = wake_at {
+                let due = at.saturating_duration_since(Instant::now());
+                timeout = Some(timeout.map_or(due, |t| t.min(due)));
+            }
+            self.notifier.wait(timeout, &mut ready);
+            if wake_at.is_some() {
+                let now = Instant::now();
+                for (&id, entry) in &self.files {
+                    if entry.follower.wake_at().is_some_and(|t| t <= now) {
+                        ready.push(id);
+                    }
+                }
+            }
+            for id in ready.drain(..) {
+                if !self.dirty.contains(&id) {
+                    self.dirty.push_back(id);
+                }
+            }
+        }
+    }
+
+    /// Copies every file's position into the checkpoints.
+    fn record_checkpoints(&mut self) {
+        if let Some(checkpoints) = &mut self.checkpoints {
+            for entry in self.files.values() {
+                if let Some(checkpoint) = entry.follower.checkpoint() {
+                    checkpoints.set(&entry.path, checkpoint);
+                }
+            }
+        }
+    }
+
+    /// Saves all positions if checkpointing is on and either `now` is set
+    /// or the last save was long enough ago.
+    fn save_checkpoints(&mut self, now: bool) -> Result<(), LogWatcherError> {
+        match &self.checkpoints {
+            Some(checkpoints) if now || checkpoints.due() => {}
+            _ => return Ok(()),
+        }
+        self.record_checkpoints();
+        match &mut self.checkpoints {
+            Some(checkpoints) => checkpoints
+                .save()
+                .map_err(|e| LogWatcherError::checkpoint(checkpoints.path(), e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Tags a failure to save checkpoints with the state file's path.
+    fn state_error(&self, err: LogWatcherError) -> Tagged {
+        let path = Arc::from(err.path().unwrap_or(Path::new("")));
+        (None, path, Err(err))
+    }
+
+    /// Follows every file in the set until the callback returns
+    /// `LogWatcherAction::Stop` or the stop handle fires. Other actions
+    /// apply to the file the event came from.
+    pub fn watch<F>(&mut self, callback: &mut F) -> StopReason
+    where
+        F: ?Sized + FnMut(&Path, Result<LogWatcherEvent, LogWatcherError>) -> LogWatcherAction,
+    {
+        self.stopped = None;
+        loop {
+            if let Some(reason) = self.stop_reason() {
+                if let Err(err) = self.save_checkpoints(true) {
+                    let (_, path, err) = self.state_error(err);
+                    callback(&path, err);
+                }
+                return reason;
+            }
+            if let Some((id, path, event)) = self.next_event(None) {
+                let action = callback(&path, event);
+                self.apply(id, action);
+            }
+        }
+    }
+}
+
+impl Drop for LogWatcherSet {
+    fn drop(&mut self) {
+        // Nobody is left to tell about a failure.
+        let _ = self.save_checkpoints(true);
+    }
+}
+
+impl Iterator for LogWatcherSet {
+    type Item = (Arc<Path>, Result<LogWatcherEvent, LogWatcherError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event(None).map(|(_, path, event)| (path, event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::thread;
+
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn next_line(set: &mut LogWatcherSet) -> Option<String> {
+        loop {
+            match set.next_timeout(Duration::from_secs(2))?.1 {
+                Ok(LogWatcherEvent::Line(line, _)) => return Some(line),
+                Ok(_) => {}
+                Err(err) => panic!("{err}"),
+            }
+        }
+    }
+
+    fn append_later(path: &Path, data: &'static [u8]) -> thread::JoinHandle<()> {
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let mut f = OpenOptions::new().append(true).open(path).unwrap();
+            f.write_all(data).unwrap();
+        })
+    }
+
+    #[test]
+    fn file_showing_up_is_watched() {
+        let file = TempFile::new(b"");
+        fs::remove_file(file.path()).unwrap();
+        let mut set = LogWatcherSet::new().unwrap();
+        set.add_allow_missing(file.path()).unwrap();
+        fs::write(file.path(), b"one\n").unwrap();
+        assert_eq!(next_line(&mut set).as_deref(), Some("one"));
+        // Use up the event for the file's creation.
+        assert!(set.next_timeout(Duration::from_millis(100)).is_none());
+        // Written while the set waits.
+        let writer = append_later(file.path(), b"two\n");
+        let waited = Instant::now();
+        assert_eq!(next_line(&mut set).as_deref(), Some("two"));
+        // Not just read once the timeout ran out.
+        assert!(waited.elapsed() < Duration::from_secs(1));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn unwatchable_file_leaves_inotify_on() {
+        let mut set = LogWatcherSet::new().unwrap();
+        assert!(set.add("/nonexistent-dir/app.log").is_err());
+        let file = TempFile::new(b"");
+        set.add(file.path()).unwrap();
+        let writer = append_later(file.path(), b"one\n");
+        let waited = Instant::now();
+        assert_eq!(next_line(&mut set).as_deref(), Some("one"));
+        // Well before the next poll.
+        assert!(waited.elapsed() < Duration::from_millis(500));
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn failed_save_on_stop_is_reported_once() {
+        let file = TempFile::new(b"one\n");
+        let mut set = LogWatcherSet::with_checkpoint("/nonexistent-dir/state").unwrap();
+        set.add(file.path()).unwrap();
+        set.stop_handle().stop();
+        assert!(matches!(
+            set.next(),
+            Some((_, Err(LogWatcherError::Checkpoint { .. })))
+        ));
+        assert!(set.next().is_none());
+    }
+}
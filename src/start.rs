@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use crate::Delimiter;
+
+/// Where to start reading a file when the watcher is registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StartPosition {
+    /// Read everything already in the file.
+    Beginning,
+    /// Skip what is already there and only report new lines.
+    #[default]
+    End,
+    /// Start with the last N complete lines, like `tail -n N -f`. Lines
+    /// end in the watcher's delimiter.
+    LastLines(usize),
+    /// Start at a byte offset. Offsets past the end of the file start at
+    /// the end.
+    Offset(u64),
+}
+
+const CHUNK: u64 = 8192;
+
+impl StartPosition {
+    /// Resolves to a byte offset in `f`, which is `len` bytes long and
+    /// split into lines by `delimiter`.
+    pub(crate) fn offset(self, f: &mut File, len: u64, delimiter: &Delimiter) -> io::Result<u64> {
+        match self {
+            StartPosition::Beginning => Ok(0),
+            StartPosition::End => Ok(len),
+            StartPosition::Offset(pos) => Ok(pos.min(len)),
+            StartPosition::LastLines(n) => last_lines(f, len, n, delimiter),
+        }
+    }
+}
+
+/// Finds where the last `n` lines start by reading backwards from the end
+/// in fixed-size chunks, so only the tail of a large file is touched.
+fn last_lines(f: &mut File, len: u64, n: usize, delimiter: &Delimiter) -> io::Result<u64> {
+    if n == 0 || len == 0 {
+        return Ok(len);
+    }
+    // Each chunk reaches this far into the one after it, so that a
+    // delimiter cut in two by the chunk boundary is found whole.
+    let overlap = delimiter.max_len() as u64 - 1;
+    let mut buf = vec![0u8; (CHUNK + overlap) as usize];
+    // Everything from here on has been looked at.
+    let mut end = len;
+    let mut seen = 0;
+    while end > 0 {
+        let start = end.saturating_sub(CHUNK);
+        let chunk = &mut buf[..((end + overlap).min(len) - start) as usize];
+        f.seek(SeekFrom::Start(start))?;
+        f.read_exact(chunk)?;
+        let mut data = &chunk[..];
+        while let Some(found) = delimiter.rfind(data) {
+            data = &data[..found.start];
+            let after = start + found.end as u64;
+            // Found with the previous chunk already, or the delimiter
+            // ending the last line, which doesn't start another one.
+            if start + found.start as u64 >= end || after == len {
+                continue;
+            }
+            seen += 1;
+            if seen == n {
+                return Ok(after);
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFile;
+
+    fn offset(contents: &[u8], start: StartPosition, delimiter: &Delimiter) -> u64 {
+        let file = TempFile::new(contents);
+        let mut f = File::open(file.path()).unwrap();
+        start
+            .offset(&mut f, contents.len() as u64, delimiter)
+            .unwrap()
+    }
+
+    #[test]
+    fn last_lines_newline() {
+        let last = |n| {
+            offset(
+                b"a\nb\nc\n",
+                StartPosition::LastLines(n),
+                &Delimiter::Newline,
+            )
+        };
+        assert_eq!(last(0), 6);
+        assert_eq!(last(1), 4);
+        assert_eq!(last(2), 2);
+        assert_eq!(last(3), 0);
+        assert_eq!(last(10), 0);
+    }
+
+    #[test]
+    fn last_lines_counts_an_unterminated_line() {
+        let start = StartPosition::LastLines(1);
+        assert_eq!(offset(b"a\nb\nhalf", start, &Delimiter::Newline), 4);
+    }
+
+    #[test]
+    fn last_lines_uses_the_delimiter() {
+        let start = StartPosition::LastLines(2);
+        assert_eq!(offset(b"a\0b\0c\0d\0", start, &Delimiter::Nul), 4);
+        assert_eq!(offset(b"a\0b\0c\0d\0", start, &Delimiter::Newline), 0);
+        assert_eq!(offset(b"a\r\nb\r\nc\r\n", start, &Delimiter::CrLf), 3);
+        let seq = Delimiter::Sequence(b"<EOR>".to_vec());
+        assert_eq!(offset(b"a<EOR>b\n<EOR>c<EOR>", start, &seq), 6);
+    }
+
+    #[test]
+    fn last_lines_finds_a_sequence_across_chunks() {
+        // The first delimiter straddles the boundary of the last chunk.
+        let mut contents = b"a".repeat(100);
+        contents.extend_from_slice(b"<EOR>");
+        contents.extend_from_slice(&b"b".repeat(8169));
+        contents.extend_from_slice(b"<EOR>");
+        contents.extend_from_slice(&b"c".repeat(10));
+        contents.extend_from_slice(b"<EOR>");
+        assert_eq!(contents.len() as u64 - CHUNK, 102);
+        let seq = Delimiter::Sequence(b"<EOR>".to_vec());
+        assert_eq!(offset(&contents, StartPosition::LastLines(2), &seq), 105);
+        assert_eq!(offset(&contents, StartPosition::LastLines(3), &seq), 0);
+    }
+
+    #[test]
+    fn offset_past_the_end() {
+        let start = StartPosition::Offset(100);
+        assert_eq!(offset(b"a\nb\n", start, &Delimiter::Newline), 4);
+    }
+}
@@ -0,0 +1,66 @@
+use std::io;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Why `LogWatcher::watch` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The callback returned `LogWatcherAction::Stop`, or
+    /// `LogWatcherAction::SkipFile` for a watcher of a single file.
+    Callback,
+    /// `StopHandle::stop` was called.
+    Handle,
+    /// A failure ended watching: the retry policy ran out or the file
+    /// stayed missing too long. `watch` returns the error instead; when
+    /// pulling events, it is the last one.
+    Failed,
+}
+
+/// Stops a running watcher from another thread.
+///
+/// Handles are cheap to clone. Stopping is permanent: once `stop` has been
+/// called, every later `watch` on the same watcher returns immediately.
+#[derive(Debug, Clone)]
+pub struct StopHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    stopped: AtomicBool,
+    waker: UnixStream,
+}
+
+impl StopHandle {
+    /// Creates a handle together with the socket the watcher polls to be
+    /// woken up when the handle fires.
+    pub(crate) fn new() -> io::Result<(StopHandle, UnixStream)> {
+        let (waker, wakee) = UnixStream::pair()?;
+        waker.set_nonblocking(true)?;
+        wakee.set_nonblocking(true)?;
+        let handle = StopHandle {
+            inner: Arc::new(Inner {
+                stopped: AtomicBool::new(false),
+                waker,
+            }),
+        };
+        Ok((handle, wakee))
+    }
+
+    pub fn stop(&self) {
+        self.inner.stopped.store(true, Ordering::SeqCst);
+        self.wake();
+    }
+
+    /// Interrupts the watcher's current wait without stopping it.
+    pub(crate) fn wake(&self) {
+        // A full socket buffer already means a pending wakeup.
+        let _ = (&self.inner.waker).write(&[1]);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.inner.stopped.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use tokio::task::JoinHandle;
+use tokio::time::Sleep;
+
+use crate::follower::Follower;
+use crate::notify::AsyncNotifier;
+use crate::{
+    Delimiter, EncodingPolicy, LineLimit, LogWatcherAction, LogWatcherError, LogWatcherEvent,
+    Multiline, RetryPolicy, StartPosition, WatchMode,
+};
+
+/// Upper bound on events read per trip to the blocking pool.
+const READ_BATCH: usize = 1024;
+
+type Batch = (Follower, VecDeque<Result<LogWatcherEvent, LogWatcherError>>);
+
+/// Async version of `LogWatcher`, available with the `tokio` feature.
+///
+/// It is a `Stream` of the same events and follows rotations the same way.
+/// Regular files can't be polled for readiness, so reads run on tokio's
+/// blocking pool, which is also how `tokio::fs` does file IO. Waiting for
+/// new data goes through the tokio reactor (inotify) or tokio timers
+/// (polling mode), so no thread is parked while the file is idle.
+///
+/// The stream ends after `LogWatcherAction::Stop` has been handled; dropping
+/// it is enough to stop watching.
+pub struct AsyncLogWatcher {
+    follower: Option<Follower>,
+    read: Option<JoinHandle<Batch>>,
+    events: VecDeque<Result<LogWatcherEvent, LogWatcherError>>,
+    notifier: AsyncNotifier,
+    caught_up: bool,
+    actions: Vec<LogWatcherAction>,
+    discard_read: bool,
+    stopped: bool,
+    retry_policy: RetryPolicy,
+    missing_timeout: Option<Duration>,
+    encoding: EncodingPolicy,
+    flush_timeout: Option<Duration>,
+    delimiter: Delimiter,
+    line_limit: Option<LineLimit>,
+    /// A multiline setting to hand to the follower with the next read.
+    multiline: Option<Option<Multiline>>,
+    /// Fires when a `LogWatcherAction::Pause` is over. A pause without an
+    /// end is only lifted by a seek.
+    paused: Option<Option<Pin<Box<Sleep>>>>,
+    /// Fires when the follower wants to be asked again even if the file
+    /// doesn't change.
+    wake: Option<Pin<Box<Sleep>>>,
+}
+
+impl AsyncLogWatcher {
+    pub async fn register<P: AsRef<Path>>(filename: P) -> Result<AsyncLogWatcher, LogWatcherError> {
+        AsyncLogWatcher::register_with_mode(filename, WatchMode::default()).await
+    }
+
+    pub async fn register_with_mode<P: AsRef<Path>>(
+        filename: P,
+        mode: WatchMode,
+    ) -> Result<AsyncLogWatcher, LogWatcherError> {
+        AsyncLogWatcher::open(filename.as_ref(), mode, StartPosition::End, false).await
+    }
+
+    pub async fn register_at<P: AsRef<Path>>(
+        filename: P,
+        start: StartPosition,
+    ) -> Result<AsyncLogWatcher, LogWatcherError> {
+        AsyncLogWatcher::open(filename.as_ref(), WatchMode::default(), start, false).await
+    }
+
+    /// Registers a file that may not exist yet, like
+    /// `LogWatcher::register_allow_missing`.
+    pub async fn register_allow_missing<P: AsRef<Path>>(
+        filename: P,
+    ) -> Result<AsyncLogWatcher, LogWatcherError> {
+        let filename = filename.as_ref();
+        AsyncLogWatcher::open(filename, WatchMode::default(), StartPosition::End, true).await
+    }
+
+    async fn open(
+        filename: &Path,
+        mode: WatchMode,
+        start: StartPosition,
+        allow_missing: bool,
+    ) -> Result<AsyncLogWatcher, LogWatcherError> {
+        let filename = filename.to_path_buf();
+        let notifier = AsyncNotifier::new(&filename, mode);
+        let follower = tokio::task::spawn_blocking(move || {
+            // Lines are counted by the default delimiter; one set later
+            // only applies from there on.
+            let delimiter = Delimiter::default();
+            if allow_missing {
+                Follower::open_or_wait(&filename, start, &delimiter)
+            } else {
+                Follower::open(&filename, start, &delimiter)
+            }
+        })
+        .await
+        .map_err(|e| LogWatcherError::Io(io::Error::other(e)))??;
+        Ok(AsyncLogWatcher {
+            follower: Some(follower),
+            read: None,
+            events: VecDeque::new(),
+            notifier,
+            caught_up: false,
+            actions: Vec::new(),
+            discard_read: false,
+            stopped: false,
+            retry_policy: RetryPolicy::default(),
+            missing_timeout: None,
+            encoding: EncodingPolicy::default(),
+            flush_timeout: None,
+            delimiter: Delimiter::default(),
+            line_limit: None,
+            multiline: None,
+            paused: None,
+            wake: None,
+        })
+    }
+
+    /// Applies an action to the watcher, like the return value of the
+    /// callback passed to `LogWatcher::watch`.
+    pub fn handle_action(&mut self, action: LogWatcherAction) {
+        match action {
+            LogWatcherAction::Stop | LogWatcherAction::SkipFile => self.stopped = true,
+            LogWatcherAction::Pause(duration) => {
+                let until = Instant::now().checked_add(duration);
+                self.paused = Some(until.map(|at| Box::pin(tokio::time::sleep_until(at.into()))));
+            }
+            LogWatcherAction::SeekToStart
+            | LogWatcherAction::SeekTo(_)
+            | LogWatcherAction::SeekToEnd => {
+                if let Some(None) = self.paused {
+                    self.paused = None;
+                }
+                // Anything read ahead is from before the seek.
+                self.events.clear();
+                self.discard_read = self.read.is_some();
+                self.caught_up = false;
+                self.actions.push(action);
+            }
+            LogWatcherAction::None => {}
+        }
+    }
+
+    /// Changes how failures reading the file, or opening it after a
+    /// rotation, are retried. The stream ends after
+    /// `LogWatcherError::GaveUp`.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Ends the stream with `LogWatcherError::FileVanished` once the file
+    /// has been missing for `timeout`.
+    pub fn set_missing_timeout(&mut self, timeout: Option<Duration>) {
+        self.missing_timeout = timeout;
+    }
+
+    /// Changes what happens to lines that are not valid UTF-8, like
+    /// `LogWatcher::set_encoding`.
+    pub fn set_encoding(&mut self, encoding: EncodingPolicy) {
+        self.encoding = encoding;
+    }
+
+    /// Delivers lines that have been waiting for their delimiter for
+    /// `timeout`, like `LogWatcher::set_flush_timeout`.
+    pub fn set_flush_timeout(&mut self, timeout: Option<Duration>) {
+        self.flush_timeout = timeout;
+    }
+
+    /// Changes what ends a line, `\n` by default.
+    pub fn set_delimiter(&mut self, delimiter: Delimiter) {
+        self.delimiter = delimiter;
+    }
+
+    /// Caps how long a line can get, like `LogWatcher::set_line_limit`.
+    pub fn set_line_limit(&mut self, limit: Option<LineLimit>) {
+        self.line_limit = limit;
+    }
+
+    /// Joins lines into records, like `LogWatcher::set_multiline`.
+    pub fn set_multiline(&mut self, multiline: Option<Multiline>) {
+        self.multiline = Some(multiline);
+    }
+
+    fn start_read(&mut self, mut follower: Follower) {
+        let actions = std::mem::take(&mut self.actions);
+        follower.set_retry_policy(self.retry_policy);
+        follower.set_missing_timeout(self.missing_timeout);
+        follower.set_encoding(self.encoding);
+        follower.set_flush_timeout(self.flush_timeout);
+        follower.set_delimiter(self.delimiter.clone());
+        follower.set_line_limit(self.line_limit);
+        if let Some(multiline) = self.multiline.take() {
+            follower.set_multiline(multiline);
+        }
+        self.read = Some(tokio::task::spawn_blocking(move || {
+            for action in &actions {
+                follower.handle_action(action);
+            }
+            let mut events = VecDeque::new();
+            while events.len() < READ_BATCH {
+                match follower.next_event() {
+                    Some(event) => events.push_back(event),
+                    None => break,
+                }
+            }
+            (follower, events)
+        }));
+    }
+}
+
+impl Stream for AsyncLogWatcher {
+    type Item = Result<LogWatcherEvent, LogWatcherError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.stopped {
+                return Poll::Ready(None);
+            }
+            if let Some(paused) = this.paused.as_mut() {
+                let over = paused
+                    .as_mut()
+                    .is_some_and(|sleep| sleep.as_mut().poll(cx).is_ready());
+                if !over {
+                    return Poll::Pending;
+                }
+                this.paused = None;
+            }
+            if let Some(event) = this.events.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+
+            if let Some(read) = this.read.as_mut() {
+                let result = match Pin::new(read).poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(result) => result,
+                };
+                this.read = None;
+                let (mut follower, events) = match result {
+                    Ok(batch) => batch,
+                    Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                    // The runtime is shutting down.
+                    Err(_) => return Poll::Ready(None),
+                };
+                this.wake = follower
+                    .wake_at()
+                    .map(|at| Box::pin(tokio::time::sleep_until(at.into())));
+                let rotated = follower.take_reopened();
+                let finished = follower.finished();
+                if !finished {
+                    this.follower = Some(follower);
+                }
+                if rotated {
+                    // Writes to the new file that landed before the watch
+                    // moved over raised no event, so read once more.
+                    this.notifier.rewatch();
+                }
+                if std::mem::take(&mut this.discard_read) {
+                    continue;
+                }
+                this.caught_up = !rotated && !finished && events.len() < READ_BATCH;
+                this.events = events;
+                continue;
+            }
+
+            if this.caught_up {
+                let woken = match this.wake.as_mut() {
+                    Some(sleep) => sleep.as_mut().poll(cx).is_ready(),
+                    None => false,
+                };
+                if !woken && this.notifier.poll_wait(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.wake = None;
+                this.caught_up = false;
+            }
+
+            match this.follower.take() {
+                Some(follower) => this.start_read(follower),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}